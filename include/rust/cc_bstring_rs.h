@@ -0,0 +1,44 @@
+/*
+ * ccommon - a cache common library.
+ * Copyright (C) 2018 Twitter, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#pragma once
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#include <stdint.h>
+
+#include <cc_bstring.h>
+
+/* NOTE: for documentation see ccommon/rust/ccommon_rs/src/bstring.rs */
+
+/* returns the length of the bstring, or UINT32_MAX if str is NULL */
+uint32_t
+bstring_len_rs(const struct bstring *str);
+
+/* memcmp-style comparison of the contents of two bstrings, where a prefix
+ * sorts before the longer string. returns -1, 0 or 1, or -2 if either
+ * argument is NULL.
+ */
+int32_t
+bstring_cmp_rs(const struct bstring *a, const struct bstring *b);
+
+
+#ifdef __cplusplus
+}
+#endif
@@ -53,6 +53,10 @@ typedef enum log_status_rs {
     LOG_STATUS_OTHER_FAILURE,
     /* You suck at programming */
     LOG_STATUS_NULL_POINTER_ERROR,
+    /* The given value is not a valid log_level_rs_e */
+    LOG_STATUS_INVALID_LEVEL,
+    /* The log directory is missing or not writable */
+    LOG_STATUS_DIR_UNAVAILABLE,
 } log_status_rs_e;
 
 struct log_config_rs {
@@ -69,6 +73,59 @@ log_status_rs_e log_shutdown_rs(struct log_handle_rs *handle, uint32_t timeout_m
 void log_destroy_handle_rs(struct log_handle_rs **h);
 
 bool log_is_setup_rs(struct log_handle_rs *handle);
+/* like log_is_setup_rs, but false rather than an abort for a NULL handle */
+bool log_is_active_rs(struct log_handle_rs *handle);
+
+/* change the level of a running logger */
+log_status_rs_e log_set_level_rs(struct log_handle_rs *handle, log_level_rs_e level);
+/* the most verbose level currently logged, or 0 if logging is off */
+uint32_t log_get_level_rs(void);
+
+/* close and reopen every thread's log file at its configured path, e.g. from
+ * a SIGHUP handler after logrotate. each thread reopens on its next write.
+ */
+log_status_rs_e log_reopen_rs(struct log_handle_rs *handle);
+
+/* flush every thread's log buffer, e.g. before a crash dump. best-effort:
+ * lines written by other threads while this runs may wait for the next flush
+ */
+log_status_rs_e log_flush_all_rs(struct log_handle_rs *handle);
+
+/* number of log lines dropped process-wide because a write failed, e.g.
+ * because the logger's buffer was full
+ */
+uint64_t log_dropped_count_rs(void);
+
+/* process-wide timing of log buffer flushes. only collected after
+ * log_set_flush_timing_rs(true), since it costs two clock reads per flush
+ */
+struct log_flush_stats_rs {
+    uint64_t count;
+    uint64_t total_ns;
+    uint64_t max_ns;
+};
+
+void log_set_flush_timing_rs(bool enabled);
+log_status_rs_e log_get_flush_stats_rs(struct log_flush_stats_rs *stats);
+
+/* Alternative to filling in a struct log_config_rs: an opaque, Rust-owned
+ * builder. Strings passed to the setters are copied, so the caller keeps
+ * ownership of them. The builder must be released exactly once with
+ * log_config_builder_destroy_rs, and may be released as soon as the handle
+ * has been created. */
+struct log_config_builder_rs;
+
+struct log_config_builder_rs* log_config_builder_create_rs(void);
+void log_config_builder_destroy_rs(struct log_config_builder_rs **b);
+
+log_status_rs_e log_config_builder_path_rs(struct log_config_builder_rs *b, const char *path);
+log_status_rs_e log_config_builder_prefix_rs(struct log_config_builder_rs *b, const char *prefix);
+log_status_rs_e log_config_builder_buf_size_rs(struct log_config_builder_rs *b, uint32_t buf_size);
+log_status_rs_e log_config_builder_level_rs(struct log_config_builder_rs *b, log_level_rs_e level);
+log_status_rs_e log_config_builder_create_dir_rs(struct log_config_builder_rs *b, bool create);
+
+/* returns NULL on failure, see stderr for details */
+struct log_handle_rs* log_create_handle_from_builder_rs(const struct log_config_builder_rs *b);
 
 #ifdef __cplusplus
 }
@@ -15,6 +15,8 @@
 */
 
 #include <cc_bstring.h>
+#include <cc_mm.h>
+#include <hash/cc_murmur3.h>
 #include <buffer/cc_buf.h>
 #include <cc_log.h>
 #include <rust/cc_log_rs.h>
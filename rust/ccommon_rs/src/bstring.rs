@@ -11,7 +11,10 @@
 //!
 //! Much like with the standard library collections (Vec, Box), one
 //! cannot simply pass their `from_raw` methods any old pointer.
-//! You must only pass pointers obtained via the `into_raw` method.
+//! You must only pass pointers obtained via the `into_raw` method,
+//! or from C's `bstring_alloc`. Every BString, including ones created on
+//! the Rust side, is allocated through cc_mm, so there is no separate
+//! "native" kind that would need to be freed differently.
 //!
 //! # Undefined Behavior
 //!
@@ -23,25 +26,55 @@
 //! [nasal demons]: http://www.catb.org/jargon/html/N/nasal-demons.html
 
 use cc_binding as bind;
-use std::borrow::Borrow;
+use ptrs;
+use std::borrow::{Borrow, Cow};
 use std::boxed::Box;
+use std::ascii;
 use std::cell::UnsafeCell;
+use std::cmp;
 use std::fmt;
-use std::fmt::Debug;
+use std::fmt::{Debug, Display, Write as FmtWrite};
 use std::fmt::Formatter;
+use std::hash::{Hash, Hasher};
+use std::io::{self, IoSlice, Read};
+use std::iter::FromIterator;
 use std::mem;
-use std::ops::{Deref, DerefMut};
+use std::ops::{Add, AddAssign, Deref, DerefMut, Range};
+use std::ptr;
 use std::slice;
-use std::str;
+use std::str::{self, Utf8Error};
+use std::sync::Arc;
+use std::sync::atomic;
 use std::borrow::BorrowMut;
 
 
 pub type CCbstring = bind::bstring;
 
+// passed to cc_mm's allocation functions so their debug logging can say
+// where an allocation came from
+const MM_CALLER: &[u8] = b"bstring.rs\0";
+
+/// Converts a Rust length to a bstring's `u32` length.
+///
+/// # Panics
+///
+/// If `len` doesn't fit. Truncating would hand C a length that doesn't
+/// match the buffer.
+#[inline]
+fn checked_len(len: usize) -> u32 {
+    assert!(len <= u32::max_value() as usize, "{} bytes is too long for a bstring", len);
+    len as u32
+}
+
 
 #[doc(hidden)]
 #[inline]
 unsafe fn raw_ptr_to_bytes<'a>(ptr: *const CCbstring) -> &'a [u8] {
+    // an empty cc_bstring has a NULL data pointer, which from_raw_parts
+    // doesn't allow even for a zero length slice
+    if (*ptr).data.is_null() {
+        return &[];
+    }
     slice::from_raw_parts(
         (*ptr).data as *const _ as *const u8,
         (*ptr).len as usize
@@ -51,6 +84,9 @@ unsafe fn raw_ptr_to_bytes<'a>(ptr: *const CCbstring) -> &'a [u8] {
 #[doc(hidden)]
 #[inline]
 unsafe fn raw_ptr_to_bytes_mut<'a>(ptr: *mut CCbstring) -> &'a mut [u8] {
+    if (*ptr).data.is_null() {
+        return &mut [];
+    }
     slice::from_raw_parts_mut(
         (*ptr).data as *mut _ as *mut u8,
         (*ptr).len as usize
@@ -100,9 +136,324 @@ impl BStr {
         str::from_utf8(&self[..]).map_err(|e| e.into())
     }
 
+    /// Views the contents as a `&str`, like `str::from_utf8`.
+    ///
+    /// ```rust
+    /// # use ccommon_rs::bstring::*;
+    /// let bs = BString::from("text");
+    /// assert_eq!(bs.to_str(), Ok("text"));
+    ///
+    /// let bad = BString::from_bytes(b"\xffoops");
+    /// assert!(bad.to_str().is_err());
+    /// ```
+    #[inline]
+    pub fn to_str(&self) -> Result<&str, Utf8Error> {
+        str::from_utf8(&self[..])
+    }
+
+    /// Views the contents as text, replacing invalid UTF-8 sequences with
+    /// U+FFFD. Only allocates if there was something to replace.
+    ///
+    /// ```rust
+    /// # use ccommon_rs::bstring::*;
+    /// let bs = BString::from_bytes(b"value=\xff\xfe!");
+    /// assert_eq!(bs.to_string_lossy(), "value=\u{FFFD}\u{FFFD}!");
+    /// ```
+    #[inline]
+    pub fn to_string_lossy(&self) -> Cow<str> {
+        String::from_utf8_lossy(&self[..])
+    }
+
     pub fn to_utf8_string(&self) -> super::Result<String> {
         self.to_utf8_str().map(|x| x.to_owned())
     }
+
+    /// Splits the contents on `delim`, borrowing each piece from this
+    /// string. Semantics match `str::split`: a trailing delimiter yields an
+    /// empty last piece, and an empty string yields a single empty piece.
+    ///
+    /// ```rust
+    /// # use ccommon_rs::bstring::*;
+    /// let bs = BString::from("get foo bar");
+    /// let parts: Vec<&[u8]> = bs.split(b' ').collect();
+    /// assert_eq!(parts, vec![&b"get"[..], &b"foo"[..], &b"bar"[..]]);
+    /// ```
+    pub fn split<'a>(&'a self, delim: u8) -> impl Iterator<Item = &'a [u8]> + 'a {
+        self[..].split(move |b| *b == delim)
+    }
+
+    /// Like `split`, but on a multi-byte delimiter such as `b"\r\n"`.
+    ///
+    /// ```rust
+    /// # use ccommon_rs::bstring::*;
+    /// let bs = BString::from("+OK\r\n");
+    /// let parts: Vec<&[u8]> = bs.split_str(b"\r\n").collect();
+    /// assert_eq!(parts, vec![&b"+OK"[..], &b""[..]]);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `delim` is empty.
+    pub fn split_str<'a>(&'a self, delim: &'a [u8]) -> SplitStr<'a> {
+        assert!(!delim.is_empty(), "split_str delimiter must not be empty");
+        SplitStr { rest: Some(&self[..]), delim }
+    }
+
+    /// Splits the contents on `delim`, starting from the end and working
+    /// backwards. Semantics match `str::rsplit`: a trailing delimiter
+    /// yields an empty first piece.
+    ///
+    /// ```rust
+    /// # use ccommon_rs::bstring::*;
+    /// let bs = BString::from("a=b=c");
+    /// let parts: Vec<&[u8]> = bs.rsplit(b'=').collect();
+    /// assert_eq!(parts, vec![&b"c"[..], &b"b"[..], &b"a"[..]]);
+    /// ```
+    pub fn rsplit<'a>(&'a self, delim: u8) -> impl Iterator<Item = &'a [u8]> + 'a {
+        self[..].rsplit(move |b| *b == delim)
+    }
+
+    /// Splits the contents on `delim`, returning at most `n` pieces. The
+    /// last piece holds the unsplit remainder, as with `str::splitn`.
+    ///
+    /// ```rust
+    /// # use ccommon_rs::bstring::*;
+    /// let bs = BString::from("key=value=with=equals");
+    /// let parts: Vec<&[u8]> = bs.splitn(2, b'=').collect();
+    /// assert_eq!(parts, vec![&b"key"[..], &b"value=with=equals"[..]]);
+    /// ```
+    pub fn splitn<'a>(&'a self, n: usize, delim: u8) -> impl Iterator<Item = &'a [u8]> + 'a {
+        self[..].splitn(n, move |b| *b == delim)
+    }
+
+    /// Returns the bytes in `range`, or `None` if the range is inverted or
+    /// extends past the end. Unlike slicing with `[a..b]` this never panics,
+    /// which makes it suitable for lengths that come off the wire.
+    pub fn get_range(&self, range: Range<usize>) -> Option<&[u8]> {
+        range.end.checked_sub(range.start)
+            .and_then(|n| self.window(range.start, n))
+    }
+
+    /// Returns a reader over the contents, for code that wants `impl Read`.
+    #[inline]
+    pub fn reader(&self) -> BStrReader {
+        BStrReader { bytes: &self[..], pos: 0 }
+    }
+
+    /// Borrows the contents as an `IoSlice` for vectored writes.
+    #[inline]
+    pub fn as_io_slice(&self) -> IoSlice {
+        IoSlice::new(&self[..])
+    }
+
+    /// Returns the contents with leading and trailing ASCII whitespace removed.
+    pub fn trim_ascii(&self) -> &[u8] {
+        let b = &self[..];
+        let start = b.iter().position(|c| !c.is_ascii_whitespace()).unwrap_or(b.len());
+        let end = b.iter().rposition(|c| !c.is_ascii_whitespace()).map_or(start, |i| i + 1);
+        &b[start..end]
+    }
+
+    /// True if the contents, ignoring surrounding ASCII whitespace, equal
+    /// `token` without regard to ASCII case. Meant for header-name style
+    /// matching, e.g. `b" Content-Length "` matches `b"content-length"`.
+    pub fn matches_token(&self, token: &[u8]) -> bool {
+        self.trim_ascii().eq_ignore_ascii_case(token)
+    }
+
+    /// Returns the offset of the first occurrence of `byte`, if any.
+    #[inline]
+    pub fn position(&self, byte: u8) -> Option<usize> {
+        self[..].iter().position(|b| *b == byte)
+    }
+
+    /// Returns the offset of the last occurrence of `byte`, if any.
+    #[inline]
+    pub fn rposition(&self, byte: u8) -> Option<usize> {
+        self[..].iter().rposition(|b| *b == byte)
+    }
+
+    /// Compares the contents to `other` as unsigned big-endian integers of
+    /// arbitrary width. The shorter side is treated as if left-padded with
+    /// zeros, so `[1]` is less than `[0, 2]` even though it sorts after it
+    /// lexicographically.
+    pub fn cmp_as_be_uint(&self, other: &[u8]) -> cmp::Ordering {
+        fn strip(b: &[u8]) -> &[u8] {
+            let first = b.iter().position(|c| *c != 0).unwrap_or(b.len());
+            &b[first..]
+        }
+
+        let (a, b) = (strip(&self[..]), strip(other));
+        a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+    }
+
+    /// Hashes the contents with ccommon's `hash_murmur3_32` and a seed of 0,
+    /// so the result matches what C code computes for the same bytes.
+    #[inline]
+    pub fn cc_hash(&self) -> u32 {
+        self.cc_hash_with_seed(0)
+    }
+
+    /// Like `cc_hash`, but with an explicit murmur3 seed.
+    pub fn cc_hash_with_seed(&self, seed: u32) -> u32 {
+        assert!(self.len() <= i32::max_value() as usize);
+        let mut out: u32 = 0;
+        unsafe {
+            bind::hash_murmur3_32(
+                self[..].as_ptr() as *const _,
+                self.len() as _,
+                seed,
+                &mut out as *mut u32 as *mut _,
+            );
+        }
+        out
+    }
+
+    /// Reads a big-endian u32 at `offset`, or `None` if it would read past the end.
+    pub fn read_u32_be(&self, offset: usize) -> Option<u32> {
+        self.window(offset, 4).map(|b| read_be(b) as u32)
+    }
+
+    /// Reads a little-endian u32 at `offset`, or `None` if it would read past the end.
+    pub fn read_u32_le(&self, offset: usize) -> Option<u32> {
+        self.window(offset, 4).map(|b| read_le(b) as u32)
+    }
+
+    /// Reads a big-endian u64 at `offset`, or `None` if it would read past the end.
+    pub fn read_u64_be(&self, offset: usize) -> Option<u64> {
+        self.window(offset, 8).map(read_be)
+    }
+
+    /// Reads a little-endian u64 at `offset`, or `None` if it would read past the end.
+    pub fn read_u64_le(&self, offset: usize) -> Option<u64> {
+        self.window(offset, 8).map(read_le)
+    }
+
+    #[inline]
+    fn window(&self, offset: usize, n: usize) -> Option<&[u8]> {
+        match offset.checked_add(n) {
+            Some(end) if end <= self.len() => Some(&self[offset..end]),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    fn window_mut(&mut self, offset: usize, n: usize) -> Option<&mut [u8]> {
+        match offset.checked_add(n) {
+            Some(end) if end <= self.len() => Some(&mut self[offset..end]),
+            _ => None,
+        }
+    }
+}
+
+/// A `Read` over a borrowed bstring that tracks how much has been consumed.
+/// Reads copy straight out of the bstring's buffer.
+///
+/// ```rust
+/// # use ccommon_rs::bstring::*;
+/// use std::io::Read;
+///
+/// let bs = BString::from("GET /");
+/// let mut rdr = bs.reader();
+/// let mut verb = [0u8; 3];
+/// rdr.read_exact(&mut verb).unwrap();
+/// assert_eq!(&verb, b"GET");
+/// assert_eq!(rdr.position(), 3);
+/// ```
+#[derive(Clone, Debug)]
+pub struct BStrReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BStrReader<'a> {
+    /// How many bytes have been read so far.
+    #[inline]
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// The bytes that haven't been read yet.
+    #[inline]
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.bytes[self.pos..]
+    }
+}
+
+impl<'a> Read for BStrReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let rest = self.remaining();
+        let n = cmp::min(buf.len(), rest.len());
+        buf[..n].copy_from_slice(&rest[..n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Iterator returned by `BStr::split_str`.
+#[derive(Clone, Debug)]
+pub struct SplitStr<'a> {
+    /// What's left to split, or `None` once the last segment is out
+    rest: Option<&'a [u8]>,
+    delim: &'a [u8],
+}
+
+impl<'a> Iterator for SplitStr<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let rest = self.rest?;
+        match rest.windows(self.delim.len()).position(|w| w == self.delim) {
+            Some(i) => {
+                self.rest = Some(&rest[i + self.delim.len()..]);
+                Some(&rest[..i])
+            }
+            None => {
+                self.rest = None;
+                Some(rest)
+            }
+        }
+    }
+}
+
+/// Builds the `IoSlice` list for a vectored write of `bufs`, in order.
+///
+/// ```rust
+/// # use ccommon_rs::bstring::*;
+/// use std::io::Write;
+///
+/// let parts = vec![BString::from("GET "), BString::from("/index")];
+/// let mut out = Vec::new();
+/// out.write_vectored(&io_slices(&parts)).unwrap();
+/// assert_eq!(&out[..], b"GET /index");
+/// ```
+pub fn io_slices<'a, T: AsRef<BStr>>(bufs: &'a [T]) -> Vec<IoSlice<'a>> {
+    bufs.iter().map(|b| b.as_ref().as_io_slice()).collect()
+}
+
+#[inline]
+fn read_be(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |acc, b| (acc << 8) | u64::from(*b))
+}
+
+#[inline]
+fn read_le(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0, |acc, b| (acc << 8) | u64::from(*b))
+}
+
+#[inline]
+fn write_be(bytes: &mut [u8], val: u64) {
+    let n = bytes.len();
+    for (i, b) in bytes.iter_mut().enumerate() {
+        *b = (val >> (8 * (n - 1 - i))) as u8;
+    }
+}
+
+#[inline]
+fn write_le(bytes: &mut [u8], val: u64) {
+    for (i, b) in bytes.iter_mut().enumerate() {
+        *b = (val >> (8 * i)) as u8;
+    }
 }
 
 
@@ -134,6 +485,20 @@ impl AsMut<CCbstring> for BStr {
     }
 }
 
+impl AsRef<[u8]> for BStr {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        &self[..]
+    }
+}
+
+impl AsMut<[u8]> for BStr {
+    #[inline]
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self[..]
+    }
+}
+
 impl Borrow<CCbstring> for BStr {
     fn borrow(&self) -> &CCbstring {
         unsafe { &*self.as_ptr() }
@@ -149,9 +514,11 @@ impl BorrowMut<CCbstring> for BStr {
 impl ToOwned for BStr {
     type Owned = BString;
 
+    /// Copies the bytes into a new BString. The bstring behind `self`
+    /// is left to its owner.
     #[inline]
     fn to_owned(&self) -> BString {
-        unsafe { BString::from_raw(self.as_ptr()).clone() }
+        unsafe { BString::from_raw_cloned(self.as_ptr()) }
     }
 }
 
@@ -234,21 +601,54 @@ unsafe impl Sync for BStr {}
 /// ```
 ///
 /// Note: if you're using BString as a buffer, it's important to
-/// know that it *will not automatically resize*. If you write past the
-/// end it will panic!.
-pub struct BString(*mut CCbstring);
+/// know that indexing *will not automatically resize*. If you write past the
+/// end it will panic! Use `extend` or `+=` to append.
+pub struct BString {
+    ptr: *mut CCbstring,
+    /// Number of bytes allocated at `data`. Always >= `len`. cc_bstring
+    /// has no notion of capacity, so this is tracked on the Rust side only
+    /// and is reset to `len` when a pointer comes back through `from_raw`.
+    cap: usize,
+}
 
 impl BString {
     pub fn new(size: u32) -> Self {
+        if size == 0 {
+            return BString::empty();
+        }
+
         let bsp: *mut CCbstring = unsafe { bind::bstring_alloc(size) };
 
         assert!(!bsp.is_null());
-        BString(bsp)
+        BString { ptr: bsp, cap: size as usize }
+    }
+
+    /// Creates an empty BString. Like an initialized C bstring, its data
+    /// pointer is NULL until something is appended, so only the struct
+    /// itself is allocated. (`bstring_alloc` can't be used for this, it
+    /// returns NULL for a zero size.)
+    pub fn empty() -> Self {
+        unsafe {
+            let bsp = bind::_cc_alloc(
+                mem::size_of::<CCbstring>() as _,
+                MM_CALLER.as_ptr() as *const _,
+                line!() as _,
+            ) as *mut CCbstring;
+            assert!(!bsp.is_null());
+            bind::bstring_init(bsp);
+            BString { ptr: bsp, cap: 0 }
+        }
     }
 
+    /// Hands the bstring over to C. An empty one gives up any capacity
+    /// reserved by `with_capacity` first, since cc_bstring asserts that an
+    /// empty bstring has NULL data.
     #[inline]
-    pub fn into_raw(bs: BString) -> *mut CCbstring {
-        let unique = bs.0;
+    pub fn into_raw(mut bs: BString) -> *mut CCbstring {
+        if bs.len() == 0 {
+            bs.set_len(0);
+        }
+        let unique = bs.ptr;
         mem::forget(bs);
         unique
     }
@@ -256,21 +656,84 @@ impl BString {
     #[inline]
     pub unsafe fn from_raw(ptr: *mut CCbstring) -> BString {
         assert!(!ptr.is_null());
-        BString(ptr)
+        BString { ptr, cap: (*ptr).len as usize }
+    }
+
+    /// Copies the contents of a bstring that stays owned by someone else,
+    /// usually C. Unlike `from_raw`, `ptr` is left alone and the caller is
+    /// still responsible for freeing it.
+    #[inline]
+    pub unsafe fn from_raw_cloned(ptr: *const CCbstring) -> BString {
+        assert!(!ptr.is_null());
+        BString::from_bytes(raw_ptr_to_bytes(ptr))
     }
 
     /// Takes byte slice `&[u8]` and copies it into an owned BString.
+    ///
+    /// # Panics
+    ///
+    /// If `s` is longer than a bstring's `u32` length can describe.
     #[inline]
     pub fn from_bytes(s: &[u8]) -> Self {
-        let bsp: *mut CCbstring = unsafe { bind::bstring_alloc(s.len() as u32) };
+        if s.is_empty() {
+            return BString::empty();
+        }
+
+        let bsp: *mut CCbstring = unsafe { bind::bstring_alloc(checked_len(s.len())) };
 
         assert!(!bsp.is_null());
 
-        let mut b = BString(bsp);
+        let mut b = BString { ptr: bsp, cap: s.len() };
         b.as_bytes_mut().clone_from_slice(&s[..]);
         b
     }
 
+    /// Creates an empty BString with room for `cap` bytes. Until something
+    /// is appended it has data but no length, which cc_bstring's C API
+    /// doesn't accept, so don't pass its `as_ptr` to C before then.
+    /// `into_raw` takes care of it.
+    pub fn with_capacity(cap: usize) -> Self {
+        let mut bs = BString::empty();
+        bs.reserve_exact(cap);
+        bs
+    }
+
+    /// Changes the length to `new_len`. Growing fills the new bytes with
+    /// `fill`, reallocating only if `new_len` exceeds the capacity.
+    /// Shrinking just lowers the length and keeps the capacity for reuse,
+    /// except that shrinking to zero frees the buffer (see `set_len`).
+    pub fn resize(&mut self, new_len: usize, fill: u8) {
+        let len = self.len();
+        if new_len > len {
+            self.reserve_exact(new_len - len);
+            unsafe {
+                let data = (*self.ptr).data as *mut u8;
+                ptr::write_bytes(data.add(len), fill, new_len - len);
+            }
+        }
+        self.set_len(new_len);
+    }
+
+    /// Sets every byte up to `len` to `byte`.
+    pub fn fill(&mut self, byte: u8) {
+        for b in self.as_bytes_mut() {
+            *b = byte;
+        }
+    }
+
+    /// Sets every byte up to `len` to zero, using writes the compiler
+    /// can't optimize away even if the buffer is about to be freed. Spare
+    /// capacity is left alone; see `ZeroizingBString` for secrets.
+    pub fn zero(&mut self) {
+        zero_volatile(self.as_bytes_mut());
+    }
+
+    /// The number of bytes the buffer can hold without reallocating.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
     /// Copies the contents of `src` into self.
     ///
     /// # Panics
@@ -286,17 +749,128 @@ impl BString {
 
     #[inline]
     pub fn as_bytes(&self) -> &[u8] {
-        unsafe { raw_ptr_to_bytes(self.0) }
+        unsafe { raw_ptr_to_bytes(self.ptr) }
     }
 
     #[inline]
     pub fn as_bytes_mut(&mut self) -> &mut [u8] {
-        unsafe { raw_ptr_to_bytes_mut(self.0) }
+        unsafe { raw_ptr_to_bytes_mut(self.ptr) }
     }
 
     #[inline]
     fn len(&self) -> usize {
-        unsafe { (*self.0).len as usize }
+        unsafe { (*self.ptr).len as usize }
+    }
+
+    /// Sets `len`, which must be within the capacity. Going to zero frees
+    /// the buffer, since cc_bstring asserts that an empty bstring has NULL
+    /// data (see `bstring_empty`).
+    fn set_len(&mut self, new_len: usize) {
+        debug_assert!(new_len <= self.cap);
+        unsafe {
+            let raw = &mut *self.ptr;
+            if new_len == 0 && !raw.data.is_null() {
+                bind::_cc_free(raw.data as *mut _, MM_CALLER.as_ptr() as *const _, line!() as _);
+                raw.data = ptr::null_mut();
+                self.cap = 0;
+            }
+            raw.len = checked_len(new_len);
+        }
+    }
+
+    /// Shortens the string to end just before the first occurrence of
+    /// `byte`, e.g. to drop NUL or space padding after a value. The bytes
+    /// past the new end are left in the buffer, unless nothing is left
+    /// before `byte`, in which case the buffer is freed. Does nothing if
+    /// `byte` doesn't occur.
+    pub fn truncate_at_byte(&mut self, byte: u8) {
+        if let Some(pos) = self.position(byte) {
+            self.set_len(pos);
+        }
+    }
+
+    /// Makes sure there's room for at least `additional` more bytes past
+    /// `len`, reallocating to exactly that size if there isn't.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the new size doesn't fit in a u32, or if the allocation
+    /// fails.
+    fn reserve_exact(&mut self, additional: usize) {
+        let needed = self.len().checked_add(additional).expect("BString length overflow");
+        if needed <= self.cap {
+            return;
+        }
+        checked_len(needed);
+
+        unsafe {
+            let raw = &mut *self.ptr;
+            let data = bind::_cc_realloc(
+                raw.data as *mut _,
+                needed as _,
+                MM_CALLER.as_ptr() as *const _,
+                line!() as _,
+            );
+            assert!(!data.is_null());
+            raw.data = data as *mut _;
+        }
+        self.cap = needed;
+    }
+
+    /// Like `reserve_exact`, but grows geometrically so that repeated
+    /// small appends are amortized.
+    fn reserve(&mut self, additional: usize) {
+        if self.len() + additional > self.cap {
+            let grow = cmp::max(additional, cmp::max(self.cap, 8));
+            self.reserve_exact(grow);
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.reserve(1);
+        unsafe {
+            let raw = &mut *self.ptr;
+            *(raw.data as *mut u8).add(raw.len as usize) = byte;
+            raw.len += 1;
+        }
+    }
+
+    /// The whole allocation, including any bytes past `len`.
+    fn allocated_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            let data = (*self.ptr).data as *mut u8;
+            if data.is_null() {
+                return &mut [];
+            }
+            slice::from_raw_parts_mut(data, self.cap)
+        }
+    }
+
+    /// Appends `bytes` to the end, growing the allocation geometrically
+    /// like `Vec::extend_from_slice`. Appending an empty slice is a no-op.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the new length doesn't fit in a u32, or if the
+    /// allocation fails.
+    pub fn extend_from_slice(&mut self, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+
+        self.reserve(bytes.len());
+
+        let old_len = self.len();
+        unsafe { (*self.ptr).len = checked_len(old_len + bytes.len()) };
+        self.as_bytes_mut()[old_len..].copy_from_slice(bytes);
+    }
+
+    /// Moves the contents of `other` onto the end of this buffer, leaving
+    /// `other` empty. Like any BString emptied by `set_len`, `other` gives
+    /// up its buffer too.
+    pub fn append(&mut self, other: &mut BString) {
+        self.extend_from_slice(other.as_bytes());
+        other.set_len(0);
     }
 
     pub fn to_utf8_str<'a>(&'a self) -> super::Result<&'a str> {
@@ -306,17 +880,114 @@ impl BString {
     pub fn to_utf8_string(&self) -> super::Result<String> {
         self.to_utf8_str().map(|x| x.to_owned())
     }
+
+    /// Removes the first `n` bytes and returns them as a new BString. The
+    /// rest is moved down to the start of this buffer, which keeps its
+    /// capacity unless nothing is left (see `set_len`). Returns `None`,
+    /// leaving `self` alone, if `n > len`.
+    pub fn split_off_prefix(&mut self, n: usize) -> Option<BString> {
+        let len = self.len();
+        if n > len {
+            return None;
+        }
+
+        let prefix = BString::from_bytes(&self.as_bytes()[..n]);
+        {
+            let bytes = self.as_bytes_mut();
+            unsafe { ptr::copy(bytes.as_ptr().add(n), bytes.as_mut_ptr(), len - n) };
+        }
+        self.set_len(len - n);
+
+        Some(prefix)
+    }
+
+    /// Writes `val` big-endian at `offset`. Returns false, leaving the
+    /// buffer untouched, if it doesn't fit.
+    pub fn write_u32_be(&mut self, offset: usize, val: u32) -> bool {
+        self.window_mut(offset, 4).map(|b| write_be(b, u64::from(val))).is_some()
+    }
+
+    /// Writes `val` little-endian at `offset`. Returns false, leaving the
+    /// buffer untouched, if it doesn't fit.
+    pub fn write_u32_le(&mut self, offset: usize, val: u32) -> bool {
+        self.window_mut(offset, 4).map(|b| write_le(b, u64::from(val))).is_some()
+    }
+
+    /// Writes `val` big-endian at `offset`. Returns false, leaving the
+    /// buffer untouched, if it doesn't fit.
+    pub fn write_u64_be(&mut self, offset: usize, val: u64) -> bool {
+        self.window_mut(offset, 8).map(|b| write_be(b, val)).is_some()
+    }
+
+    /// Writes `val` little-endian at `offset`. Returns false, leaving the
+    /// buffer untouched, if it doesn't fit.
+    pub fn write_u64_le(&mut self, offset: usize, val: u64) -> bool {
+        self.window_mut(offset, 8).map(|b| write_le(b, val)).is_some()
+    }
+}
+
+const DEBUG_PREVIEW_LEN: usize = 64;
+
+/// Debug-formats bytes like a byte string literal, `b"ab\x00"`, escaping
+/// anything that isn't printable ASCII and eliding everything after the
+/// first `DEBUG_PREVIEW_LEN` bytes.
+struct Preview<'a>(&'a [u8]);
+
+impl<'a> Debug for Preview<'a> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        let shown = cmp::min(self.0.len(), DEBUG_PREVIEW_LEN);
+        f.write_str("b\"")?;
+        for b in &self.0[..shown] {
+            for c in ascii::escape_default(*b) {
+                f.write_char(c as char)?;
+            }
+        }
+        f.write_str("\"")?;
+        if shown < self.0.len() {
+            f.write_str("...")?;
+        }
+        Ok(())
+    }
+}
+
+impl Debug for BStr {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("BStr")
+            .field("len", &self.len())
+            .field("data", &Preview(&self[..]))
+            .finish()
+    }
+}
+
+impl Display for BStr {
+    /// Writes the contents as text, with invalid UTF-8 replaced by U+FFFD.
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.write_str(&self.to_string_lossy())
+    }
 }
 
 impl Debug for BString {
     fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
         f.debug_struct("BString")
             .field("len", &self.len())
-            .field("data", &self.as_bytes())
+            .field("data", &Preview(self.as_bytes()))
             .finish()
     }
 }
 
+impl Display for BString {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        Display::fmt(&**self, f)
+    }
+}
+
+impl Default for BString {
+    #[inline]
+    fn default() -> Self {
+        BString::empty()
+    }
+}
+
 impl PartialEq for BString {
     #[inline]
     fn eq(&self, other: &BString) -> bool {
@@ -324,10 +995,50 @@ impl PartialEq for BString {
     }
 }
 
+impl Eq for BString {}
+
+impl Hash for BString {
+    // hash the contents, not the pointer
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_bytes().hash(state)
+    }
+}
+
+impl PartialEq<[u8]> for BString {
+    #[inline]
+    fn eq(&self, other: &[u8]) -> bool {
+        self.as_bytes() == other
+    }
+}
+
+impl<'a> PartialEq<&'a [u8]> for BString {
+    #[inline]
+    fn eq(&self, other: &&'a [u8]) -> bool {
+        self.as_bytes() == *other
+    }
+}
+
+impl PartialEq<str> for BString {
+    #[inline]
+    fn eq(&self, other: &str) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl<'a> PartialEq<&'a str> for BString {
+    #[inline]
+    fn eq(&self, other: &&'a str) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
 impl Drop for BString {
     #[inline]
     fn drop(&mut self) {
-        unsafe { bind::bstring_free(&mut self.0) };
+        #[cfg(test)]
+        test::on_free(self.allocated_mut());
+        unsafe { bind::bstring_free(&mut self.ptr) };
     }
 }
 
@@ -344,14 +1055,14 @@ impl Deref for BString {
 
     #[inline]
     fn deref(&self) -> &BStr {
-        unsafe { BStr::from_ptr(self.0) }
+        unsafe { BStr::from_ptr(self.ptr) }
     }
 }
 
 impl DerefMut for BString {
     #[inline]
     fn deref_mut(&mut self) -> &mut BStr {
-        unsafe { BStr::from_ptr_mut(self.0) }
+        unsafe { BStr::from_ptr_mut(self.ptr) }
     }
 }
 
@@ -368,20 +1079,80 @@ impl AsRef<BStr> for BString {
     }
 }
 
-impl Borrow<BStr> for BString {
+impl AsRef<[u8]> for BString {
     #[inline]
-    fn borrow(&self) -> &BStr {
-        &*self
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
     }
 }
 
-impl From<Vec<u8>> for BString {
+impl AsMut<[u8]> for BString {
     #[inline]
-    fn from(v: Vec<u8>) -> Self {
+    fn as_mut(&mut self) -> &mut [u8] {
+        self.as_bytes_mut()
+    }
+}
+
+impl Borrow<BStr> for BString {
+    #[inline]
+    fn borrow(&self) -> &BStr {
+        &*self
+    }
+}
+
+impl From<Vec<u8>> for BString {
+    #[inline]
+    fn from(v: Vec<u8>) -> Self {
         BString::from_bytes(&v[..])
     }
 }
 
+impl FromIterator<u8> for BString {
+    fn from_iter<I: IntoIterator<Item=u8>>(iter: I) -> Self {
+        let mut bs = BString::empty();
+        bs.extend(iter);
+        bs
+    }
+}
+
+impl<'a> FromIterator<&'a u8> for BString {
+    fn from_iter<I: IntoIterator<Item=&'a u8>>(iter: I) -> Self {
+        iter.into_iter().cloned().collect()
+    }
+}
+
+impl Extend<u8> for BString {
+    fn extend<I: IntoIterator<Item=u8>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        // for exact-size iterators this is the only allocation
+        self.reserve_exact(iter.size_hint().0);
+        for b in iter {
+            self.push(b);
+        }
+    }
+}
+
+impl<'a> Extend<&'a u8> for BString {
+    fn extend<I: IntoIterator<Item=&'a u8>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().cloned());
+    }
+}
+
+impl<'a, T: AsRef<[u8]> + ?Sized> Add<&'a T> for BString {
+    type Output = BString;
+
+    fn add(mut self, rhs: &'a T) -> BString {
+        self.extend_from_slice(rhs.as_ref());
+        self
+    }
+}
+
+impl<'a, T: AsRef<[u8]> + ?Sized> AddAssign<&'a T> for BString {
+    fn add_assign(&mut self, rhs: &'a T) {
+        self.extend_from_slice(rhs.as_ref());
+    }
+}
+
 impl From<BString> for Vec<u8> {
     #[inline]
     fn from(bs: BString) -> Self {
@@ -391,6 +1162,13 @@ impl From<BString> for Vec<u8> {
     }
 }
 
+impl<'a> From<&'a [u8]> for BString {
+    #[inline]
+    fn from(s: &'a [u8]) -> Self {
+        BString::from_bytes(s)
+    }
+}
+
 impl From<Box<[u8]>> for BString {
     #[inline]
     fn from(b: Box<[u8]>) -> Self {
@@ -405,13 +1183,282 @@ impl<'a> From<&'a str> for BString {
     }
 }
 
+impl From<String> for BString {
+    #[inline]
+    fn from(s: String) -> Self {
+        BString::from_bytes(s.as_bytes())
+    }
+}
+
 unsafe impl Send for BString {}
 unsafe impl Sync for BString {}
 
+/// Returns the length of the bstring at `p`, or `u32::MAX` if `p` is NULL.
+#[no_mangle]
+pub unsafe extern "C" fn bstring_len_rs(p: *const CCbstring) -> u32 {
+    match ptrs::null_check(p as *mut CCbstring) {
+        Ok(p) => (*p).len,
+        Err(_) => u32::max_value(),
+    }
+}
+
+/// Compares the contents of two bstrings like memcmp, with the shorter
+/// one ordering first when one is a prefix of the other. Returns -1, 0 or
+/// 1, or -2 if either pointer is NULL.
+#[no_mangle]
+pub unsafe extern "C" fn bstring_cmp_rs(a: *const CCbstring, b: *const CCbstring) -> i32 {
+    let (a, b) = match (ptrs::null_check(a as *mut CCbstring), ptrs::null_check(b as *mut CCbstring)) {
+        (Ok(a), Ok(b)) => (BStr::from_ptr(a), BStr::from_ptr(b)),
+        _ => return -2,
+    };
+
+    match a[..].cmp(&b[..]) {
+        cmp::Ordering::Less => -1,
+        cmp::Ordering::Equal => 0,
+        cmp::Ordering::Greater => 1,
+    }
+}
+
+#[cfg(feature = "serde")]
+mod serde_impls {
+    use super::BString;
+    use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
+    use serde::ser::{Serialize, Serializer};
+    use std::fmt;
+
+    impl Serialize for BString {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_bytes(self.as_bytes())
+        }
+    }
+
+    struct BStringVisitor;
+
+    impl<'de> Visitor<'de> for BStringVisitor {
+        type Value = BString;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a byte string")
+        }
+
+        fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<BString, E> {
+            Ok(BString::from_bytes(v))
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<BString, E> {
+            Ok(BString::from_bytes(v.as_bytes()))
+        }
+
+        // self-describing formats like JSON hand bytes over as a sequence
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<BString, A::Error> {
+            let mut bs = BString::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(b) = seq.next_element::<u8>()? {
+                bs.push(b);
+            }
+            Ok(bs)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for BString {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<BString, D::Error> {
+            deserializer.deserialize_bytes(BStringVisitor)
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+        use bincode;
+        use serde_json;
+
+        #[test]
+        fn test_serde_json_round_trip() {
+            let bs = BString::from_bytes(b"a\x00b\xff");
+            let json = serde_json::to_string(&bs).unwrap();
+            assert_eq!(json, "[97,0,98,255]");
+
+            let back: BString = serde_json::from_str(&json).unwrap();
+            assert_eq!(back, bs);
+
+            let empty: BString = serde_json::from_str("[]").unwrap();
+            assert!(empty.is_empty());
+        }
+
+        #[test]
+        fn test_bincode_round_trip() {
+            let bs = BString::from("cache entry");
+            let encoded = bincode::serialize(&bs).unwrap();
+            let back: BString = bincode::deserialize(&encoded).unwrap();
+            assert_eq!(back, bs);
+
+            let empty = BString::empty();
+            let encoded = bincode::serialize(&empty).unwrap();
+            let back: BString = bincode::deserialize(&encoded).unwrap();
+            assert!(back.is_empty());
+        }
+    }
+}
+
+/// A read-only view of a range of bytes inside a shared, owned BString.
+///
+/// Views share the backing allocation through an `Arc`, so cloning one
+/// (or carving a sub-view out of it) is cheap. The underlying BString is
+/// freed when the last view referring to it is dropped.
+///
+/// ```rust
+/// # use ccommon_rs::bstring::*;
+/// let whole = SharedBString::from(BString::from("header:body"));
+/// let header = whole.slice(0, 6).unwrap();
+/// let body = whole.slice(7, 4).unwrap();
+/// drop(whole);
+///
+/// assert_eq!(&header[..], b"header");
+/// assert_eq!(&body[..], b"body");
+/// ```
+#[derive(Clone)]
+pub struct SharedBString {
+    buf: Arc<BString>,
+    offset: usize,
+    len: usize,
+}
+
+impl SharedBString {
+    /// Returns a view of `len` bytes starting at `offset`, relative to the
+    /// start of this view. Returns `None` if the range doesn't fit.
+    pub fn slice(&self, offset: usize, len: usize) -> Option<SharedBString> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.len => Some(SharedBString {
+                buf: self.buf.clone(),
+                offset: self.offset + offset,
+                len,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl From<BString> for SharedBString {
+    fn from(bs: BString) -> Self {
+        let len = bs.len();
+        SharedBString { buf: Arc::new(bs), offset: 0, len }
+    }
+}
+
+impl Deref for SharedBString {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        &self.buf.as_bytes()[self.offset..self.offset + self.len]
+    }
+}
+
+impl Debug for SharedBString {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("SharedBString")
+            .field("offset", &self.offset)
+            .field("len", &self.len)
+            .field("data", &&self[..])
+            .finish()
+    }
+}
+
+/// Overwrites `buf` with zeros using volatile writes, so the compiler can't
+/// elide the stores even though the memory is about to be freed.
+#[inline(never)]
+pub(crate) fn zero_volatile(buf: &mut [u8]) {
+    for b in buf.iter_mut() {
+        unsafe { ptr::write_volatile(b, 0) };
+    }
+    atomic::compiler_fence(atomic::Ordering::SeqCst);
+}
+
+/// A `BString` that wipes its contents before the memory is released.
+/// Use it for key material and other secrets that shouldn't linger in
+/// freed heap memory.
+///
+/// ```rust
+/// # use ccommon_rs::bstring::*;
+/// let key = ZeroizingBString::from(BString::from("hunter2"));
+/// assert_eq!(&key[..], b"hunter2");
+/// drop(key); // buffer is zeroed, then freed
+/// ```
+pub struct ZeroizingBString(BString);
+
+impl ZeroizingBString {
+    /// Zeroes the whole buffer now. The length is unchanged.
+    pub fn zeroize(&mut self) {
+        // include any spare capacity, it may hold old contents
+        zero_volatile(self.0.allocated_mut());
+    }
+}
+
+impl From<BString> for ZeroizingBString {
+    fn from(bs: BString) -> Self {
+        ZeroizingBString(bs)
+    }
+}
+
+impl Drop for ZeroizingBString {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl Deref for ZeroizingBString {
+    type Target = BStr;
+
+    #[inline]
+    fn deref(&self) -> &BStr {
+        &*self.0
+    }
+}
+
+impl DerefMut for ZeroizingBString {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut BStr {
+        &mut *self.0
+    }
+}
+
+impl Debug for ZeroizingBString {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        // don't leak the secret into logs
+        f.debug_struct("ZeroizingBString")
+            .field("len", &self.0.len())
+            .finish()
+    }
+}
+
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::cell::RefCell;
+
+    thread_local! {
+        // the data pointer a test is watching, and its contents when freed
+        static FREED: RefCell<(usize, Option<Vec<u8>>)> = RefCell::new((0, None));
+    }
+
+    /// Called by `BString`'s drop with the whole allocation, just before
+    /// it's handed to `bstring_free`.
+    pub(super) fn on_free(alloc: &[u8]) {
+        FREED.with(|f| {
+            let mut f = f.borrow_mut();
+            if !alloc.is_empty() && alloc.as_ptr() as usize == f.0 {
+                f.1 = Some(alloc.to_vec());
+            }
+        });
+    }
+
+    /// Runs `f` and returns what the buffer at `data` held when it was
+    /// freed, or `None` if `f` didn't free it.
+    fn contents_when_freed<F: FnOnce()>(data: *const u8, f: F) -> Option<Vec<u8>> {
+        FREED.with(|w| *w.borrow_mut() = (data as usize, None));
+        f();
+        FREED.with(|w| w.borrow_mut().1.take())
+    }
 
     #[test]
     fn test_raw_ptr_to_bytes() {
@@ -450,6 +1497,515 @@ mod test {
         assert_eq!(bs.as_bytes(), "wat".as_bytes());
     }
 
+    #[test]
+    fn test_bstring_from_c_allocated() {
+        // allocated the way C code would, then owned and freed by Rust
+        let mut bs = unsafe { BString::from_raw(bind::bstring_alloc(3)) };
+        bs.as_bytes_mut().copy_from_slice(b"foo");
+        bs += "bar";
+        assert_eq!(&bs[..], b"foobar");
+
+        // and back again: a Rust-built BString handed over to C's free
+        let mut ptr = BString::into_raw(BString::from("to C"));
+        unsafe { bind::bstring_free(&mut ptr) };
+        assert!(ptr.is_null());
+    }
+
+    #[test]
+    fn test_checked_len() {
+        assert_eq!(checked_len(0), 0);
+        assert_eq!(checked_len(u32::max_value() as usize), u32::max_value());
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    #[should_panic(expected = "too long for a bstring")]
+    fn test_checked_len_overflow() {
+        checked_len(u32::max_value() as usize + 1);
+    }
+
+    #[test]
+    fn test_bstring_from_slice() {
+        let data = b"abc\x00def";
+        let bs = BString::from(&data[..]);
+        assert_eq!(bs.len(), data.len());
+        assert_eq!(&bs[..], &data[..]);
+
+        assert!(BString::from(&b""[..]).is_empty());
+    }
+
+    #[test]
+    fn test_bstring_from_raw_cloned() {
+        let mut ptr = unsafe { bind::bstring_alloc(3) };
+        unsafe { raw_ptr_to_bytes_mut(ptr).copy_from_slice(b"foo") };
+
+        let bs = unsafe { BString::from_raw_cloned(ptr) };
+        assert_ne!(bs.as_ptr(), ptr);
+
+        // the copy outlives the original
+        unsafe { bind::bstring_free(&mut ptr) };
+        assert!(ptr.is_null());
+        assert_eq!(&bs[..], b"foo");
+    }
+
+    #[test]
+    fn test_bstr_to_owned() {
+        let mut ptr = unsafe { bind::bstring_alloc(3) };
+        let owned = {
+            let bsr = unsafe { BStr::from_ptr_mut(ptr) };
+            bsr.copy_from_slice(b"bar");
+            bsr.to_owned()
+        };
+
+        // to_owned must not have taken (or freed) the original
+        unsafe { bind::bstring_free(&mut ptr) };
+        assert_eq!(&owned[..], b"bar");
+    }
+
+    fn sum<T: AsRef<[u8]> + ?Sized>(bytes: &T) -> u32 {
+        bytes.as_ref().iter().map(|&b| b as u32).sum()
+    }
+
+    fn bump<T: AsMut<[u8]> + ?Sized>(bytes: &mut T) {
+        for b in bytes.as_mut() {
+            *b += 1;
+        }
+    }
+
+    #[test]
+    fn test_as_ref_as_mut_bytes() {
+        let mut bs = BString::from("abc");
+        assert_eq!(sum(&bs), 294);
+        bump(&mut bs);
+        assert_eq!(&bs[..], b"bcd");
+
+        {
+            let bsr: &mut BStr = &mut bs;
+            bump(bsr);
+            assert_eq!(sum(bsr), 300);
+        }
+        let bsr: &BStr = &bs;
+        assert_eq!(sum(bsr), 300);
+    }
+
+    #[test]
+    fn test_bstring_debug_and_display() {
+        let bs = BString::from("hello");
+        assert_eq!(format!("{:?}", bs), r#"BString { len: 5, data: b"hello" }"#);
+        assert_eq!(format!("{}", bs), "hello");
+
+        let empty = BString::empty();
+        assert_eq!(format!("{:?}", empty), r#"BString { len: 0, data: b"" }"#);
+        assert_eq!(format!("{}", empty), "");
+
+        let bin = BString::from_bytes(b"a\x00\xff\"");
+        assert_eq!(format!("{:?}", bin), r#"BString { len: 4, data: b"a\x00\xff\"" }"#);
+        assert_eq!(format!("{:?}", &*bin), r#"BStr { len: 4, data: b"a\x00\xff\"" }"#);
+        assert_eq!(format!("{}", bin), "a\u{0}\u{FFFD}\"");
+
+        let long = BString::from_bytes(&[b'x'; 100][..]);
+        let dbg = format!("{:?}", long);
+        assert!(dbg.contains(&format!("b\"{}\"...", "x".repeat(64))));
+        assert!(dbg.starts_with("BString { len: 100,"));
+    }
+
+    #[test]
+    fn test_bstring_clone_is_deep() {
+        let orig = BString::from("original");
+        let mut copy = orig.clone();
+        assert_eq!(orig, copy);
+        assert_ne!(orig.as_ptr(), copy.as_ptr());
+        assert_ne!(orig.as_bytes().as_ptr(), copy.as_bytes().as_ptr());
+
+        copy[0] = b'O';
+        assert_eq!(&copy[..], b"Original");
+        assert_eq!(&orig[..], b"original");
+    }
+
+    #[test]
+    fn test_bstring_as_map_key() {
+        use std::collections::HashMap;
+
+        let mut m = HashMap::new();
+        m.insert(BString::from("alpha"), 1);
+        m.insert(BString::from("beta"), 2);
+        m.insert(BString::from_bytes(b"\x00\xff"), 3);
+
+        assert_eq!(m.get(&BString::from("alpha")), Some(&1));
+        assert_eq!(m.get(&BString::from(String::from("beta"))), Some(&2));
+        assert_eq!(m.get(&BString::from_bytes(&[0, 255])), Some(&3));
+        assert_eq!(m.get(&BString::from("gamma")), None);
+    }
+
+    #[test]
+    fn test_bstring_eq_slices() {
+        let bs = BString::from("abc");
+        assert!(bs == "abc");
+        assert!(bs == *"abc");
+        assert!(bs == &b"abc"[..]);
+        assert!(bs == b"abc"[..]);
+        assert!(bs != "abd");
+        assert!(bs != &b"ab"[..]);
+    }
+
+    #[test]
+    fn test_bstring_from_string() {
+        let bs = BString::from(format!("{}-{}", "key", 42));
+        assert_eq!(&bs[..], b"key-42");
+        assert_eq!(bs.len(), 6);
+    }
+
+    #[test]
+    fn test_bstring_fill_and_zero() {
+        let mut bs = BString::from("reused buffer");
+        bs.fill(0xAB);
+        assert!(bs.iter().all(|&b| b == 0xAB));
+        assert_eq!(bs.len(), 13);
+
+        bs.zero();
+        assert_eq!(&bs[..], &[0u8; 13][..]);
+
+        // no data pointer to write through
+        let mut empty = BString::empty();
+        empty.fill(0xAB);
+        empty.zero();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn test_zeroizing_bstring_wipes_contents() {
+        let mut key = ZeroizingBString::from(BString::from("s3cr3t"));
+        let data = unsafe { (*key.as_ptr()).data };
+        key.zeroize();
+
+        // the same allocation is still live and now all zeros
+        assert_eq!(unsafe { (*key.as_ptr()).data }, data);
+        assert_eq!(&key[..], &[0u8; 6][..]);
+    }
+
+    #[test]
+    fn test_zeroizing_bstring_drop_wipes_spare_capacity() {
+        let mut bs = BString::with_capacity(16);
+        bs.extend_from_slice(b"old secret");
+        bs.truncate_at_byte(b' ');
+        assert_eq!(&bs[..], b"old");
+
+        let key = ZeroizingBString::from(bs);
+        let data = unsafe { (*key.as_ptr()).data } as *const u8;
+
+        // all 16 bytes, not just the 3 in use
+        assert_eq!(contents_when_freed(data, || drop(key)), Some(vec![0u8; 16]));
+
+        // a plain BString is freed as is
+        let bs = BString::from("plain");
+        let data = unsafe { (*bs.as_ptr()).data } as *const u8;
+        assert_eq!(contents_when_freed(data, || drop(bs)), Some(b"plain".to_vec()));
+    }
+
+    #[test]
+    fn test_zeroizing_bstring_debug_hides_contents() {
+        let key = ZeroizingBString::from(BString::from("s3cr3t"));
+        let dbg = format!("{:?}", key);
+        assert!(!dbg.contains("s3cr3t"));
+        assert!(dbg.contains("len: 6"));
+    }
+
+    #[test]
+    fn test_bstr_reader_chunks() {
+        let bs = BString::from("0123456789");
+        let mut rdr = bs.reader();
+
+        let mut out = Vec::new();
+        let mut chunk = [0u8; 4];
+        loop {
+            let n = rdr.read(&mut chunk).unwrap();
+            if n == 0 {
+                break;
+            }
+            assert!(n == 4 || (n == 2 && rdr.position() == 10));
+            out.extend_from_slice(&chunk[..n]);
+        }
+
+        assert_eq!(&out[..], &bs[..]);
+        assert_eq!(rdr.position(), 10);
+        assert!(rdr.remaining().is_empty());
+        assert_eq!(rdr.read(&mut chunk).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_io_slices_write_vectored() {
+        use std::io::Write;
+
+        let parts = vec![BString::from("abc"), BString::from("de"), BString::from("fghi")];
+        let slices = io_slices(&parts);
+        assert_eq!(slices.len(), 3);
+        assert_eq!(&*parts[1].as_io_slice(), b"de");
+
+        let mut sink: Vec<u8> = Vec::new();
+        let n = sink.write_vectored(&slices).unwrap();
+        assert_eq!(n, 9);
+        assert_eq!(&sink[..], b"abcdefghi");
+    }
+
+    #[test]
+    fn test_bstring_add_assign() {
+        let mut bs = BString::from("key");
+        bs += b":";
+        bs += &b"value"[..];
+        bs += "!";
+        assert_eq!(&bs[..], b"key:value!");
+        assert_eq!(bs.len(), 10);
+
+        let bs = BString::from("a") + b"bc";
+        assert_eq!(&bs[..], b"abc");
+    }
+
+    #[test]
+    fn test_bstring_extend() {
+        let mut bs = BString::from("ab");
+        bs.extend(b"cdef".iter().filter(|b| **b != b'e'));
+        bs.extend(vec![b'g', b'h']);
+        bs.extend(Vec::<u8>::new());
+        assert_eq!(&bs[..], b"abcdfgh");
+        assert_eq!(bs.len(), 7);
+    }
+
+    #[test]
+    fn test_bstring_from_exact_size_iter_allocates_once() {
+        let bs: BString = (0u8..100).collect();
+        // growing one byte at a time would have left extra capacity
+        assert_eq!(bs.capacity(), 100);
+        assert_eq!(bs.len(), 100);
+        assert_eq!(bs[99], 99);
+
+        let mut bs = BString::from("ab");
+        bs.extend(b"cdef".iter());
+        assert_eq!(bs.capacity(), 6);
+        assert_eq!(&bs[..], b"abcdef");
+    }
+
+    #[test]
+    fn test_bstring_extend_without_size_hint_grows() {
+        let mut bs = BString::from("x");
+        bs.extend((0..20u8).filter(|b| b % 2 == 0).map(|b| b'a' + b));
+        assert_eq!(&bs[..], b"xacegikmoqs");
+        assert!(bs.capacity() >= bs.len());
+
+        let empty: BString = Vec::<u8>::new().into_iter().collect();
+        assert_eq!(empty.len(), 0);
+        assert_eq!(&empty[..], b"");
+    }
+
+    #[test]
+    fn test_bstring_empty() {
+        let bs = BString::default();
+        assert!(bs.is_empty());
+        assert_eq!(bs.capacity(), 0);
+        assert_eq!(bs.as_bytes(), b"");
+        assert_eq!(bs, BString::empty());
+
+        // the zero length constructors all agree, and clone/drop cleanly
+        assert!(BString::new(0).is_empty());
+        assert!(BString::from("").is_empty());
+        assert!(BString::from_bytes(&[]).clone().is_empty());
+        drop(bs);
+
+        let mut bs = BString::empty();
+        bs += "now with data";
+        assert_eq!(&bs[..], b"now with data");
+    }
+
+    #[test]
+    fn test_bstring_with_capacity_and_resize() {
+        let mut bs = BString::with_capacity(8);
+        assert!(bs.is_empty());
+        assert_eq!(bs.capacity(), 8);
+
+        bs.resize(4, b'a');
+        assert_eq!(&bs[..], b"aaaa");
+        assert_eq!(bs.capacity(), 8);
+
+        // past capacity reallocates
+        bs.resize(10, b'b');
+        assert_eq!(&bs[..], b"aaaabbbbbb");
+        assert_eq!(bs.capacity(), 10);
+
+        // shrinking keeps the allocation around
+        bs.resize(2, b'z');
+        assert_eq!(&bs[..], b"aa");
+        assert_eq!(bs.capacity(), 10);
+
+        // and growing back into it fills with the new byte
+        bs.resize(5, b'c');
+        assert_eq!(&bs[..], b"aaccc");
+        assert_eq!(bs.capacity(), 10);
+
+        // empty means NULL data as far as C is concerned
+        bs.resize(0, 0);
+        assert!(bs.is_empty());
+        assert_eq!(bs.capacity(), 0);
+        assert!(unsafe { (*bs.as_ptr()).data.is_null() });
+        assert!(unsafe { bind::bstring_empty(bs.as_ptr()) });
+    }
+
+    #[test]
+    fn test_bstring_into_raw_releases_reserved_capacity() {
+        let bs = BString::with_capacity(8);
+        let raw = BString::into_raw(bs);
+        unsafe {
+            assert!((*raw).data.is_null());
+            assert!(bind::bstring_empty(raw));
+            drop(BString::from_raw(raw));
+        }
+    }
+
+    #[test]
+    fn test_bstring_extend_from_slice() {
+        let mut bs = BString::empty();
+        bs.extend_from_slice(b"");
+        assert!(bs.is_empty());
+
+        bs.extend_from_slice(b"abc");
+        assert_eq!(&bs[..], b"abc");
+
+        bs.extend_from_slice(b"");
+        assert_eq!(&bs[..], b"abc");
+
+        for _ in 0..100 {
+            bs.extend_from_slice(b"de");
+        }
+        assert_eq!(bs.len(), 203);
+        assert_eq!(&bs[..7], b"abcdede");
+        assert_eq!(&bs[201..], b"de");
+    }
+
+    #[test]
+    fn test_bstring_append() {
+        let mut a = BString::from("head");
+        let mut b = BString::from("tail");
+
+        a.append(&mut b);
+        assert_eq!(&a[..], b"headtail");
+        assert!(b.is_empty());
+        assert_eq!(b.capacity(), 0);
+        assert!(unsafe { bind::bstring_empty(b.as_ptr()) });
+
+        // appending an empty buffer, and into an empty one
+        a.append(&mut b);
+        assert_eq!(&a[..], b"headtail");
+
+        let mut c = BString::empty();
+        c.append(&mut a);
+        assert_eq!(&c[..], b"headtail");
+        assert!(a.is_empty());
+    }
+
+    #[test]
+    fn test_bstring_len_rs() {
+        let bs = BString::from("abcde");
+        assert_eq!(unsafe { bstring_len_rs(bs.as_ptr()) }, 5);
+        assert_eq!(unsafe { bstring_len_rs(BString::empty().as_ptr()) }, 0);
+        assert_eq!(unsafe { bstring_len_rs(ptr::null()) }, u32::max_value());
+    }
+
+    #[test]
+    fn test_bstring_cmp_rs() {
+        let abc = BString::from("abc");
+        let abc2 = BString::from("abc");
+        let ab = BString::from("ab");
+        let abd = BString::from("abd");
+        let empty = BString::empty();
+
+        unsafe {
+            assert_eq!(bstring_cmp_rs(abc.as_ptr(), abc2.as_ptr()), 0);
+            assert_eq!(bstring_cmp_rs(empty.as_ptr(), empty.as_ptr()), 0);
+
+            // a prefix sorts before the longer string
+            assert_eq!(bstring_cmp_rs(ab.as_ptr(), abc.as_ptr()), -1);
+            assert_eq!(bstring_cmp_rs(abc.as_ptr(), ab.as_ptr()), 1);
+            assert_eq!(bstring_cmp_rs(empty.as_ptr(), ab.as_ptr()), -1);
+
+            assert_eq!(bstring_cmp_rs(abc.as_ptr(), abd.as_ptr()), -1);
+            assert_eq!(bstring_cmp_rs(abd.as_ptr(), ab.as_ptr()), 1);
+
+            assert_eq!(bstring_cmp_rs(ptr::null(), abc.as_ptr()), -2);
+            assert_eq!(bstring_cmp_rs(abc.as_ptr(), ptr::null()), -2);
+        }
+    }
+
+    #[test]
+    fn test_bstr_split() {
+        let bs = BString::from("SET key 0 0 5\r\n");
+        let fields: Vec<&[u8]> = bs.split(b' ').collect();
+        assert_eq!(fields, vec![&b"SET"[..], b"key", b"0", b"0", b"5\r\n"]);
+
+        // a trailing delimiter leaves an empty last segment
+        let bs = BString::from("a,b,");
+        let fields: Vec<&[u8]> = bs.split(b',').collect();
+        assert_eq!(fields, vec![&b"a"[..], b"b", b""]);
+
+        let empty = BString::empty();
+        let fields: Vec<&[u8]> = empty.split(b',').collect();
+        assert_eq!(fields, vec![&b""[..]]);
+    }
+
+    #[test]
+    fn test_bstr_split_str() {
+        let bs = BString::from("*2\r\n$3\r\nGET\r\n");
+        let lines: Vec<&[u8]> = bs.split_str(b"\r\n").collect();
+        assert_eq!(lines, vec![&b"*2"[..], b"$3", b"GET", b""]);
+
+        // segments borrow from the original buffer
+        assert_eq!(lines[2].as_ptr(), bs[8..].as_ptr());
+
+        // a lone \r or \n isn't a delimiter
+        let bs = BString::from("a\rb\nc");
+        let lines: Vec<&[u8]> = bs.split_str(b"\r\n").collect();
+        assert_eq!(lines, vec![&b"a\rb\nc"[..]]);
+
+        let empty = BString::empty();
+        let lines: Vec<&[u8]> = empty.split_str(b"\r\n").collect();
+        assert_eq!(lines, vec![&b""[..]]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_bstr_split_str_empty_delim() {
+        BString::from("abc").split_str(b"").count();
+    }
+
+    #[test]
+    fn test_bstring_split_off_prefix() {
+        let mut bs = BString::from("HDR1payload");
+        let hdr = bs.split_off_prefix(4).unwrap();
+        assert_eq!(&hdr[..], b"HDR1");
+        assert_eq!(&bs[..], b"payload");
+        assert_eq!(bs.capacity(), 11);
+
+        let all = bs.split_off_prefix(7).unwrap();
+        assert_eq!(&all[..], b"payload");
+        assert!(bs.is_empty());
+        assert_eq!(bs.capacity(), 0);
+        assert!(unsafe { bind::bstring_empty(bs.as_ptr()) });
+
+        let mut bs = BString::from("abc");
+        assert!(bs.split_off_prefix(4).is_none());
+        assert_eq!(&bs[..], b"abc");
+
+        let none = bs.split_off_prefix(0).unwrap();
+        assert!(none.is_empty());
+        assert_eq!(&bs[..], b"abc");
+    }
+
+    #[test]
+    fn test_bstring_from_iter() {
+        let bs: BString = b"a1b2c3".iter().filter(|b| b.is_ascii_alphabetic()).collect();
+        assert_eq!(bs.as_bytes(), b"abc");
+
+        let bs: BString = (b'x'..=b'z').collect();
+        assert_eq!(bs.as_bytes(), b"xyz");
+    }
+
     #[test]
     fn test_bstring_into_raw_pointer_remains_valid() {
         let bsp: *mut CCbstring;
@@ -486,6 +2042,184 @@ mod test {
         unsafe { BString::from_raw(ptr) };
     }
 
+    #[test]
+    fn test_bstr_splitn() {
+        let bs = BString::from("key=value=with=equals");
+        let parts: Vec<&[u8]> = bs.splitn(2, b'=').collect();
+        assert_eq!(parts, vec![&b"key"[..], &b"value=with=equals"[..]]);
+
+        let parts: Vec<&[u8]> = bs.splitn(1, b'=').collect();
+        assert_eq!(parts, vec![&b"key=value=with=equals"[..]]);
+    }
+
+    #[test]
+    fn test_bstr_rsplit_trailing_delimiter() {
+        let bs = BString::from("a,b,");
+        let parts: Vec<&[u8]> = bs.rsplit(b',').collect();
+        assert_eq!(parts, vec![&b""[..], &b"b"[..], &b"a"[..]]);
+    }
+
+    #[test]
+    fn test_shared_bstring_views_outlive_original() {
+        let original = SharedBString::from(BString::from("abcdefgh"));
+        let left = original.slice(0, 5).unwrap();
+        let right = original.slice(3, 5).unwrap();
+        let inner = right.slice(1, 2).unwrap();
+        assert_eq!(Arc::strong_count(&original.buf), 4);
+
+        drop(original);
+        assert_eq!(&left[..], b"abcde");
+        assert_eq!(&right[..], b"defgh");
+        assert_eq!(&inner[..], b"ef");
+
+        let weak = Arc::downgrade(&left.buf);
+        drop(left);
+        drop(right);
+        assert!(weak.upgrade().is_some());
+
+        drop(inner);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_shared_bstring_slice_out_of_range() {
+        let whole = SharedBString::from(BString::from("abc"));
+        assert!(whole.slice(0, 4).is_none());
+        assert!(whole.slice(4, 0).is_none());
+        assert!(whole.slice(usize::max_value(), 2).is_none());
+        assert_eq!(&whole.slice(3, 0).unwrap()[..], b"");
+    }
+
+    #[test]
+    fn test_bstr_trim_ascii() {
+        assert_eq!(BString::from(" \t abc \r\n").trim_ascii(), b"abc");
+        assert_eq!(BString::from("a b").trim_ascii(), b"a b");
+        assert_eq!(BString::from("   ").trim_ascii(), b"");
+    }
+
+    #[test]
+    fn test_bstr_matches_token() {
+        assert!(BString::from("  Content-Length\t").matches_token(b"content-length"));
+        assert!(BString::from("HOST").matches_token(b"Host"));
+        assert!(BString::from("host").matches_token(b"host"));
+        assert!(!BString::from(" hosts ").matches_token(b"host"));
+        assert!(!BString::from("ho st").matches_token(b"host"));
+    }
+
+    #[test]
+    fn test_bstr_cmp_as_be_uint() {
+        use std::cmp::Ordering;
+
+        // lexicographically [1] > [0, 2], numerically 1 < 2
+        assert_eq!(BString::from_bytes(&[1]).cmp_as_be_uint(&[0, 2]), Ordering::Less);
+        assert_eq!(BString::from_bytes(&[0, 2]).cmp_as_be_uint(&[1]), Ordering::Greater);
+        // [0, 0, 1] vs [1]: equal magnitude despite different widths
+        assert_eq!(BString::from_bytes(&[0, 0, 1]).cmp_as_be_uint(&[1]), Ordering::Equal);
+        // same width falls back to byte order
+        assert_eq!(BString::from_bytes(&[1, 0]).cmp_as_be_uint(&[0, 255]), Ordering::Greater);
+        assert_eq!(BString::from_bytes(&[0, 0]).cmp_as_be_uint(&[]), Ordering::Equal);
+        assert_eq!(BString::from_bytes(&[2, 0]).cmp_as_be_uint(&[1, 255, 255]), Ordering::Less);
+    }
+
+    #[test]
+    fn test_bstr_position() {
+        let bs = BString::from("a=b=c");
+        assert_eq!(bs.position(b'='), Some(1));
+        assert_eq!(bs.rposition(b'='), Some(3));
+        assert_eq!(bs.position(b'a'), Some(0));
+        assert_eq!(bs.rposition(b'c'), Some(4));
+        assert_eq!(bs.position(b'x'), None);
+        assert_eq!(bs.rposition(b'x'), None);
+    }
+
+    #[test]
+    fn test_bstring_truncate_at_byte() {
+        let mut bs = BString::from("key\0\0\0\0");
+        bs.truncate_at_byte(0);
+        assert_eq!(&bs[..], b"key");
+        assert_eq!(bs.capacity(), 7);
+
+        // nothing before the delimiter, so C must see NULL data
+        let mut bs = BString::from(" padded");
+        bs.truncate_at_byte(b' ');
+        assert_eq!(bs.len(), 0);
+        assert_eq!(&bs[..], b"");
+        assert_eq!(bs.capacity(), 0);
+        assert!(unsafe { (*bs.as_ptr()).data.is_null() });
+        assert!(unsafe { bind::bstring_empty(bs.as_ptr()) });
+
+        let mut bs = BString::from("no-delim");
+        bs.truncate_at_byte(b' ');
+        assert_eq!(&bs[..], b"no-delim");
+    }
+
+    #[test]
+    fn test_bstr_cc_hash() {
+        // reference values for MurmurHash3_x86_32
+        assert_eq!(BString::empty().cc_hash(), 0);
+        assert_eq!(BString::empty().cc_hash_with_seed(1), 0x514e28b7);
+        assert_eq!(BString::from("hello").cc_hash(), 0x248bfa47);
+        assert_eq!(
+            BString::from("The quick brown fox jumps over the lazy dog").cc_hash(),
+            0x2e4ff723
+        );
+        assert_eq!(BString::from("aaaa").cc_hash_with_seed(0x9747b28c), 0x5a97808a);
+        assert_eq!(BString::from("Hello, world!").cc_hash_with_seed(0x9747b28c), 0x24884cba);
+        assert_eq!(BString::from_bytes(&[0, 0, 0, 0]).cc_hash(), 0x2362f9de);
+    }
+
+    #[test]
+    fn test_bstr_get_range() {
+        let bs = BString::from("abcdef");
+
+        assert_eq!(bs.get_range(1..4), Some(&b"bcd"[..]));
+        assert_eq!(bs.get_range(0..6), Some(&b"abcdef"[..]));
+        assert_eq!(bs.get_range(6..6), Some(&b""[..]));
+
+        // partially out of range
+        assert_eq!(bs.get_range(4..7), None);
+        // fully out of range
+        assert_eq!(bs.get_range(10..20), None);
+        // inverted
+        assert_eq!(bs.get_range(Range { start: 4, end: 2 }), None);
+    }
+
+    #[test]
+    fn test_read_integers() {
+        let bs = BString::from(vec![0xff, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08]);
+
+        assert_eq!(bs.read_u32_be(1), Some(0x01020304));
+        assert_eq!(bs.read_u32_le(1), Some(0x04030201));
+        assert_eq!(bs.read_u32_be(5), Some(0x05060708));
+        assert_eq!(bs.read_u64_be(1), Some(0x0102030405060708));
+        assert_eq!(bs.read_u64_le(1), Some(0x0807060504030201));
+
+        assert_eq!(bs.read_u32_be(6), None);
+        assert_eq!(bs.read_u64_le(2), None);
+        assert_eq!(bs.read_u32_le(usize::max_value()), None);
+    }
+
+    #[test]
+    fn test_write_integers() {
+        let mut bs = BString::from(vec![0u8; 10]);
+
+        assert!(bs.write_u32_be(0, 0xdeadbeef));
+        assert!(bs.write_u32_le(4, 0xdeadbeef));
+        assert_eq!(&bs[..8], &[0xde, 0xad, 0xbe, 0xef, 0xef, 0xbe, 0xad, 0xde]);
+        assert_eq!(bs.read_u32_be(0), Some(0xdeadbeef));
+        assert_eq!(bs.read_u32_le(4), Some(0xdeadbeef));
+
+        assert!(bs.write_u64_le(2, 0x0102030405060708));
+        assert_eq!(bs.read_u64_le(2), Some(0x0102030405060708));
+        assert!(bs.write_u64_be(2, 0x0102030405060708));
+        assert_eq!(&bs[2..], &[1, 2, 3, 4, 5, 6, 7, 8]);
+
+        let before = bs.as_bytes().to_vec();
+        assert!(!bs.write_u32_be(7, 1));
+        assert!(!bs.write_u64_be(3, 1));
+        assert_eq!(bs.as_bytes(), &before[..]);
+    }
+
     #[test]
     fn test_bstring_as_io_write() {
         use std::io::*;
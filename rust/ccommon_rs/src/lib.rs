@@ -20,9 +20,13 @@ extern crate crossbeam;
 extern crate failure;
 #[macro_use]
 extern crate failure_derive;
+#[macro_use]
 extern crate lazy_static;
+extern crate libc;
 #[macro_use]
 extern crate log as rslog;
+#[cfg(feature = "serde")]
+extern crate serde;
 extern crate tempfile;
 extern crate time;
 extern crate thread_local;
@@ -31,6 +35,10 @@ extern crate thread_id;
 #[cfg(test)]
 #[macro_use]
 extern crate rusty_fork;
+#[cfg(all(test, feature = "serde"))]
+extern crate bincode;
+#[cfg(any(test, feature = "json"))]
+extern crate serde_json;
 
 use std::result;
 
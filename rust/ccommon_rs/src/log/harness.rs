@@ -0,0 +1,195 @@
+// ccommon - a cache common library.
+// Copyright (C) 2018 Twitter, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Test support for exercising a single `CLogger` end to end: format a
+//! record, write it, flush, and read the file back. Also holds the
+//! shared setup for tests that only format records, and `MtLogDir` for
+//! tests that drive the whole mt logger.
+
+use super::*;
+use std::env;
+use std::fs;
+use tempfile::{self, TempDir};
+
+const HARNESS_BUF_SIZE: u32 = 4096;
+
+pub struct LogTestHarness {
+    clogger: CLogger,
+    cfg: LogConfig,
+    path: PathBuf,
+    buf: Vec<u8>,
+    // last, so the directory outlives the logger's open file
+    _tmpdir: TempDir,
+}
+
+/// A config builder for tests that only format records and never open
+/// a file.
+pub fn config() -> LogConfigBuilder {
+    let mut b = LogConfigBuilder::default();
+    b.path(env::temp_dir().to_str().unwrap().to_owned());
+    b
+}
+
+/// Formats `record` with `cfg` and returns the line as a string.
+pub fn format_line(cfg: &LogConfig, record: &Record, line: &LineInfo) -> Result<String> {
+    let mut buf = Vec::new();
+    let sz = format(record, &mut buf, cfg, line)?;
+    Ok(str::from_utf8(&buf[..sz])?.to_owned())
+}
+
+impl LogTestHarness {
+    pub fn new() -> Result<LogTestHarness> {
+        LogTestHarness::with_buf_size(HARNESS_BUF_SIZE)
+    }
+
+    pub fn with_buf_size(buf_size: u32) -> Result<LogTestHarness> {
+        ensure_cc_log_setup();
+        LogTestHarness::open(buf_size)
+    }
+
+    /// Like `with_buf_size`, but leaves calling cc_log's `log_setup` to
+    /// the caller.
+    pub fn open(buf_size: u32) -> Result<LogTestHarness> {
+        let tmpdir = tempfile::tempdir()?;
+        let path = tmpdir.path().join("harness.log");
+
+        let cfg = LogConfigBuilder::default()
+            .path(tmpdir.path().to_str().unwrap().to_owned())
+            .prefix(String::from("harness"))
+            .build()?;
+
+        let clogger = unsafe { CLogger::open(path.to_str().unwrap(), buf_size)? };
+
+        Ok(LogTestHarness { clogger, cfg, path, buf: Vec::new(), _tmpdir: tmpdir })
+    }
+
+    /// Formats `msg` the same way `PerThreadLog` would and writes it.
+    pub fn log(&mut self, level: Level, msg: &str) -> bool {
+        let sz = format(
+            &Record::builder()
+                .args(format_args!("{}", msg))
+                .level(level)
+                .module_path(Some("harness"))
+                .build(),
+            &mut self.buf,
+            &self.cfg,
+            &LineInfo::default(),
+        ).unwrap();
+
+        unsafe { self.clogger.write(&self.buf[..sz]) }
+    }
+
+    /// Writes `bytes` as-is, skipping formatting.
+    pub fn write(&self, bytes: &[u8]) -> bool {
+        unsafe { self.clogger.write(bytes) }
+    }
+
+    pub fn flush(&self) {
+        unsafe { self.clogger.flush() };
+    }
+
+    /// Flushes the logger and returns everything written so far.
+    pub fn read_all(&self) -> Result<String> {
+        self.flush();
+        Ok(fs::read_to_string(&self.path)?)
+    }
+}
+
+/// A temp directory for tests that set up the process-wide mt logger.
+/// Those tests have to be forked, since only one logger can be set up at a
+/// time.
+pub struct MtLogDir {
+    tmpdir: TempDir,
+}
+
+impl MtLogDir {
+    pub fn new() -> Result<MtLogDir> {
+        ensure_cc_log_setup();
+        Ok(MtLogDir { tmpdir: tempfile::tempdir()? })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.tmpdir.path()
+    }
+
+    /// A config builder for `<prefix>.<thread name>.log` files in this
+    /// directory.
+    pub fn config(&self, prefix: &str) -> LogConfigBuilder {
+        let mut b = LogConfigBuilder::default();
+        b.path(self.path().to_str().unwrap().to_owned())
+            .prefix(String::from(prefix));
+        b
+    }
+
+    /// Reads the file `name` in this directory.
+    pub fn read(&self, name: &str) -> Result<String> {
+        Ok(fs::read_to_string(self.path().join(name))?)
+    }
+
+    /// Sets up the logger with `cfg`, runs `f` on a thread named `thread`,
+    /// shuts the logger down and returns what that thread's file holds.
+    pub fn run<F>(&self, cfg: &LogConfigBuilder, thread: &str, f: F) -> Result<String>
+        where F: FnOnce() + Send + 'static
+    {
+        let cfg = cfg.build()?;
+        let path = cfg.to_path_buf(thread);
+
+        let mut handle = log_setup_safe(cfg)?;
+        on_thread(thread, f);
+        handle.shutdown(time::Duration::milliseconds(SHUTDOWN_TIMEOUT_MS as i64));
+
+        Ok(fs::read_to_string(path)?)
+    }
+}
+
+/// Runs `f` on a thread named `name` and waits for it to finish. Panics if
+/// `f` does.
+pub fn on_thread<F>(name: &str, f: F)
+    where F: FnOnce() + Send + 'static
+{
+    thread::Builder::new().name(name.to_owned()).spawn(f).unwrap().join().unwrap();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_harness_roundtrip() {
+        let mut h = LogTestHarness::new().unwrap();
+        assert!(h.log(Level::Info, "hello harness"));
+        assert!(h.log(Level::Error, "second line"));
+
+        let s = h.read_all().unwrap();
+        let lines: Vec<&str> = s.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("INFO  [harness] hello harness"));
+        assert!(lines[1].ends_with("ERROR [harness] second line"));
+    }
+
+    #[test]
+    fn test_short_line_after_long_line() {
+        let mut h = LogTestHarness::new().unwrap();
+        let long = "q".repeat(500);
+        assert!(h.log(Level::Info, &long));
+        assert!(h.log(Level::Info, "tiny"));
+
+        let s = h.read_all().unwrap();
+        let lines: Vec<&str> = s.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].ends_with("INFO  [harness] tiny"), "{}", lines[1]);
+        assert!(!lines[1].contains('q'));
+    }
+}
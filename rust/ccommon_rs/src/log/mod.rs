@@ -75,20 +75,42 @@ pub use super::Result;
 use cc_binding as bind;
 use crossbeam::sync::ArcCell;
 use failure;
+use libc;
 use ptrs;
 use rslog;
 use bstring::BStr;
-use std::cell::RefCell;
-use std::ffi::CString;
-use std::io::{Cursor, Write};
-use std::path::PathBuf;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::fmt;
+use std::io::{self, Write};
+use std::mem;
+use std::panic;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::ptr;
-use std::sync::Arc;
+use std::str;
+use std::sync::{Arc, Mutex, Once, TryLockError, Weak, ONCE_INIT};
+use std::sync::mpsc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_BOOL_INIT, ATOMIC_USIZE_INIT};
 use std::thread;
+use tempfile;
 use thread_id;
 use thread_local::CachedThreadLocal;
 use time;
 
+#[macro_use]
+mod ratelimit;
+pub mod reader;
+mod tee;
+#[cfg(test)]
+mod harness;
+
+pub use self::tee::TeeLogger;
+
 
 // TODO(simms): add C-side setup code here.
 
@@ -108,6 +130,9 @@ pub enum LoggingError {
     )]
     CreationError { path: String, buf_size: u32 },
 
+    #[fail(display = "log directory {} is unavailable: {}", path, reason)]
+    LogDirUnavailable { path: String, reason: String },
+
 }
 
 impl From<SetLoggerError> for LoggingError {
@@ -117,23 +142,47 @@ impl From<SetLoggerError> for LoggingError {
 }
 
 
+/// Lines `CLogger::write` failed to write (e.g. the buffer was full),
+/// across every logger in the process.
+static DROPPED_WRITES: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Returns how many log lines have been dropped because a write failed.
+pub fn dropped_count() -> usize {
+    DROPPED_WRITES.load(Ordering::Relaxed)
+}
+
+/// C accessor for `dropped_count`.
+#[no_mangle]
+pub extern "C" fn log_dropped_count_rs() -> u64 {
+    dropped_count() as u64
+}
+
 #[doc(hidden)]
 pub struct CLogger(*mut bind::logger);
 
 impl CLogger {
     pub unsafe fn from_raw(p: *mut bind::logger) -> super::Result<CLogger> {
-        ptrs::null_check(p).map(CLogger).map_err(|e| e.into())
+        ptrs::null_check_named(p, "logger").map(CLogger).map_err(|e| e.into())
     }
 
     pub unsafe fn write(&self, msg: &[u8]) -> bool {
         let b = bind::log_write(self.0, msg.as_ptr() as *mut i8, msg.len() as u32);
         if !b {
-            eprintln!("failed to write to log: {:#?}", &msg);
+            DROPPED_WRITES.fetch_add(1, Ordering::Relaxed);
+            limited_eprintln!("failed to write to log: {:#?}", &msg);
         }
         b
     }
 
-    pub unsafe fn flush(&self) { bind::log_flush(self.0); }
+    pub unsafe fn flush(&self) {
+        if FLUSH_TIMING.load(Ordering::Relaxed) {
+            let start = time::precise_time_ns();
+            bind::log_flush(self.0);
+            record_flush(time::precise_time_ns() - start);
+        } else {
+            bind::log_flush(self.0);
+        }
+    }
 
     pub unsafe fn open(path: &str, buf_size: u32) -> super::Result<CLogger> {
         let p = bind::log_create(CString::new(path)?.into_raw(), buf_size);
@@ -143,6 +192,16 @@ impl CLogger {
             .map(CLogger)
     }
 
+    /// Writes `banner` as a line of its own, appending a newline if it
+    /// doesn't already end with one.
+    pub unsafe fn write_banner(&self, banner: &str) -> bool {
+        if banner.ends_with('\n') {
+            self.write(banner.as_bytes())
+        } else {
+            self.write(format!("{}\n", banner).as_bytes())
+        }
+    }
+
     pub fn as_mut_ptr(&mut self) -> *mut bind::logger { self.0 }
 }
 
@@ -152,24 +211,244 @@ impl Drop for CLogger {
     }
 }
 
-fn format(record: &Record, buf: &mut Vec<u8>) -> Result<usize> {
-    let tm = time::now_utc();
+/// Which clock the timestamp at the start of each line comes from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Clock {
+    /// Wall-clock date and time, UTC. Readable, but can jump backwards.
+    Wall,
+    /// Wall-clock date and time in the local timezone, followed by its
+    /// offset from UTC (e.g. `-0700`) so lines stay unambiguous.
+    Local,
+    /// Nanoseconds since the logger was set up, from a monotonic clock.
+    /// Never goes backwards, so it's suitable for measuring intervals.
+    Monotonic,
+}
+
+/// Per-line values computed by the caller of `format`.
+#[derive(Default)]
+struct LineInfo {
+    /// The sequence number, if `sequence_numbers` is on
+    seq: Option<u64>,
+    /// Nanoseconds since the logger started, written instead of the wall
+    /// clock time when `clock` is `Clock::Monotonic`
+    elapsed_ns: u64,
+    /// The wall clock time to stamp the line with, if not now. Only tests
+    /// set this.
+    now: Option<time::Tm>,
+}
+
+/// The layout of each line written to the log.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    /// `[seq] timestamp LEVEL [module] message`, for people.
+    Text,
+    /// One JSON object per line, for log shippers:
+    /// `{"ts":"...","level":"INFO","module":"...","msg":"..."}`. A `seq`
+    /// field is added when sequence numbers are on, and with the `kv`
+    /// feature a `kv` object holds any structured fields as strings.
+    /// `continuation_prefix` doesn't apply, newlines in the message are
+    /// escaped instead.
+    Json,
+}
+
+/// Replaces the contents of `buf` with the formatted line, keeping its
+/// allocation, and returns the line's length (which is `buf.len()`).
+fn format(record: &Record, buf: &mut Vec<u8>, cfg: &LogConfig, line: &LineInfo) -> Result<usize> {
+    buf.clear();
+
+    match cfg.format {
+        LogFormat::Text => format_text(buf, record, cfg, line)?,
+        LogFormat::Json => format_json(buf, record, cfg, line)?,
+    }
+
+    debug_assert!(str::from_utf8(buf).is_ok(), "format produced invalid UTF-8");
+
+    Ok(buf.len())
+}
+
+fn write_timestamp<W: Write>(w: &mut W, cfg: &LogConfig, line: &LineInfo) -> Result<()> {
+    match cfg.clock {
+        Clock::Wall => write_wall_time(w, &line.now.unwrap_or_else(time::now_utc))?,
+        Clock::Local => {
+            let tm = line.now.unwrap_or_else(time::now);
+            write_wall_time(w, &tm)?;
+            let off = tm.tm_utcoff;
+            let sign = if off < 0 { '-' } else { '+' };
+            let mins = off.abs() / 60;
+            write!(w, " {}{:02}{:02}", sign, mins / 60, mins % 60)?;
+        }
+        Clock::Monotonic => write!(w, "{}", line.elapsed_ns)?,
+    }
+    Ok(())
+}
+
+fn write_wall_time<W: Write>(w: &mut W, tm: &time::Tm) -> Result<()> {
+    let ts = time::strftime("%Y-%m-%d %H:%M:%S", tm).unwrap();
+    // microseconds, tm_nsec is nanoseconds
+    write!(w, "{}.{:06}", ts, tm.tm_nsec / 1_000)?;
+    Ok(())
+}
+
+fn format_text<W: Write>(curs: &mut W, record: &Record, cfg: &LogConfig, line: &LineInfo) -> Result<()> {
+    if let Some((ref host, pid)) = cfg.host_and_pid {
+        write!(curs, "{}[{}] ", host, pid)?;
+    }
 
-    let mut curs = Cursor::new(buf);
+    if let Some(seq) = line.seq {
+        write!(curs, "{} ", seq)?;
+    }
 
-    let ts = time::strftime("%Y-%m-%d %H:%M:%S", &tm).unwrap();
+    write_timestamp(curs, cfg, line)?;
+    write!(curs, " ")?;
 
-    writeln!(
+    write!(
         curs,
-        "{}.{:06} {:<5} [{}] {}",
-        ts,
-        tm.tm_nsec,
+        "{:<5} [{}] ",
         record.level().to_string(),
         record.module_path().unwrap_or_default(),
-        record.args()
     )?;
 
-    Ok(curs.position() as usize)
+    match cfg.continuation_prefix {
+        Some(ref prefix) => {
+            // only pay for rendering the message up front in multi-line mode
+            let msg = record.args().to_string();
+            let mut lines = msg.split('\n');
+            if let Some(first) = lines.next() {
+                write!(curs, "{}", first)?;
+            }
+            for line in lines {
+                write!(curs, "\n{}{}", prefix, line)?;
+            }
+        }
+        None => write!(curs, "{}", record.args())?,
+    }
+
+    #[cfg(feature = "kv")]
+    write_key_values(curs, record)?;
+
+    writeln!(curs)?;
+    Ok(())
+}
+
+fn format_json<W: Write>(curs: &mut W, record: &Record, cfg: &LogConfig, line: &LineInfo) -> Result<()> {
+    write!(curs, "{{")?;
+    if let Some((ref host, pid)) = cfg.host_and_pid {
+        write!(curs, "\"host\":")?;
+        write_json_str(curs, host)?;
+        write!(curs, ",\"pid\":{},", pid)?;
+    }
+
+    if let Some(seq) = line.seq {
+        write!(curs, "\"seq\":{},", seq)?;
+    }
+
+    write!(curs, "\"ts\":\"")?;
+    write_timestamp(curs, cfg, line)?;
+    write!(curs, "\",\"level\":\"{}\",\"module\":", record.level())?;
+    write_json_str(curs, record.module_path().unwrap_or_default())?;
+    write!(curs, ",\"msg\":")?;
+    write_json_str(curs, &record.args().to_string())?;
+
+    #[cfg(feature = "kv")]
+    {
+        let pairs = collect_key_values(record)?;
+        if !pairs.is_empty() {
+            write!(curs, ",\"kv\":{{")?;
+            for (i, (k, v)) in pairs.iter().enumerate() {
+                if i > 0 {
+                    write!(curs, ",")?;
+                }
+                write_json_str(curs, k)?;
+                write!(curs, ":")?;
+                write_json_str(curs, v)?;
+            }
+            write!(curs, "}}")?;
+        }
+    }
+
+    writeln!(curs, "}}")?;
+    Ok(())
+}
+
+/// Writes `s` as a quoted JSON string, escaping quotes, backslashes and
+/// control characters.
+fn write_json_str<W: Write>(w: &mut W, s: &str) -> Result<()> {
+    w.write_all(b"\"")?;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        let esc: Cow<str> =
+            match c {
+                '"' => "\\\"".into(),
+                '\\' => "\\\\".into(),
+                '\n' => "\\n".into(),
+                '\r' => "\\r".into(),
+                '\t' => "\\t".into(),
+                c if (c as u32) < 0x20 => format!("\\u{:04x}", c as u32).into(),
+                _ => continue,
+            };
+        w.write_all(s[start..i].as_bytes())?;
+        w.write_all(esc.as_bytes())?;
+        start = i + c.len_utf8();
+    }
+    w.write_all(s[start..].as_bytes())?;
+    w.write_all(b"\"")?;
+    Ok(())
+}
+
+/// The record's structured fields, rendered to strings and sorted by key so
+/// the layout doesn't depend on call site ordering.
+#[cfg(feature = "kv")]
+fn collect_key_values(record: &Record) -> Result<Vec<(String, String)>> {
+    use rslog::kv::{self, Key, Value, VisitSource};
+
+    struct Collect(Vec<(String, String)>);
+
+    impl<'kvs> VisitSource<'kvs> for Collect {
+        fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> ::std::result::Result<(), kv::Error> {
+            self.0.push((key.as_str().to_owned(), value.to_string()));
+            Ok(())
+        }
+    }
+
+    let mut pairs = Collect(Vec::new());
+    record.key_values()
+        .visit(&mut pairs)
+        .map_err(|e| format_err!("failed to read key/values: {}", e))?;
+    pairs.0.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(pairs.0)
+}
+
+/// Appends the record's structured fields as ` k=v` pairs. Values that are
+/// empty or contain whitespace, `=`, quotes or control characters are
+/// written as quoted, escaped strings so the line can still be split on
+/// spaces.
+#[cfg(feature = "kv")]
+fn write_key_values<W: Write>(w: &mut W, record: &Record) -> Result<()> {
+    for (k, v) in collect_key_values(record)? {
+        let quote = v.is_empty() || v.chars().any(|c| {
+            c.is_whitespace() || c.is_control() || c == '=' || c == '"'
+        });
+        if quote {
+            write!(w, " {}={:?}", k, v)?;
+        } else {
+            write!(w, " {}={}", k, v)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Replaces any invalid UTF-8 sequences in `buf[..sz]` with U+FFFD, returning
+/// the new length of the formatted line. Valid input is left untouched.
+fn enforce_utf8(buf: &mut Vec<u8>, sz: usize) -> usize {
+    let fixed = match String::from_utf8_lossy(&buf[..sz]) {
+        Cow::Borrowed(_) => return sz,
+        Cow::Owned(s) => s,
+    };
+
+    buf.clear();
+    buf.extend_from_slice(fixed.as_bytes());
+    buf.len()
 }
 
 #[repr(u32)]
@@ -183,14 +462,18 @@ pub enum LoggerStatus {
     CreationError = 5,
     OtherFailure = 6,
     NullPointerError = 7,
+    InvalidLevel = 8,
+    LogDirUnavailable = 9,
 }
 
 impl From<LoggingError> for LoggerStatus {
     fn from(e: LoggingError) -> Self {
+        // no catch-all arm: a new LoggingError variant must pick a status here
         match e {
             LoggingError::LoggerRegistrationFailure => LoggerStatus::RegistrationFailure,
             LoggingError::LoggingAlreadySetUp => LoggerStatus::LoggerAlreadySetError,
             LoggingError::CreationError{..} => LoggerStatus::CreationError,
+            LoggingError::LogDirUnavailable{..} => LoggerStatus::LogDirUnavailable,
         }
     }
 }
@@ -218,10 +501,14 @@ impl From<usize> for ModuleState {
     }
 }
 
-#[cfg(test)]
-pub(in log) struct LogMetrics(*mut bind::log_metrics_st);
+/// Owns the `log_metrics_st` that cc_log's `log_setup` records into.
+///
+/// cc_log keeps the pointer it's given, so the metrics must outlive every
+/// logger: close all `CLogger`s and call `log_teardown` before dropping
+/// this. Most callers want `ensure_cc_log_setup`, which keeps its metrics
+/// alive for the rest of the process.
+pub struct LogMetrics(*mut bind::log_metrics_st);
 
-#[cfg(test)]
 impl LogMetrics {
     pub fn new() -> Self {
         let ptr = unsafe { bind::log_metrics_create() };
@@ -232,15 +519,122 @@ impl LogMetrics {
     pub fn as_mut_ptr(&mut self) -> *mut bind::log_metrics_st { self.0 }
 }
 
-#[cfg(test)]
+impl Default for LogMetrics {
+    fn default() -> Self {
+        LogMetrics::new()
+    }
+}
+
 impl Drop for LogMetrics {
     fn drop(&mut self) {
         unsafe { bind::log_metrics_destroy(&mut self.0) }
     }
 }
 
+static CC_LOG_SETUP: Once = ONCE_INIT;
+
+/// Calls cc_log's `log_setup` exactly once per process, handing it a
+/// crate-owned `LogMetrics` that lives until the process exits. Returns
+/// true if this call performed the setup, false if it had already been done.
+///
+/// `log_setup` must run before any `CLogger` is created, and calling it
+/// twice leaks the first metrics struct, so Rust callers should go through
+/// this rather than calling `bind::log_setup` directly.
+pub fn ensure_cc_log_setup() -> bool {
+    let mut ran = false;
+    CC_LOG_SETUP.call_once(|| {
+        let mut metrics = LogMetrics::new();
+        unsafe { bind::log_setup(metrics.as_mut_ptr()) };
+        // cc_log holds onto this pointer for the rest of the process
+        mem::forget(metrics);
+        ran = true;
+    });
+    ran
+}
+
+static FLUSH_TIMING: AtomicBool = ATOMIC_BOOL_INIT;
+
+lazy_static! {
+    // a Mutex rather than atomics since 64 bit atomics aren't available on
+    // every target, and it's only taken when flush timing is on
+    static ref FLUSH_STATS: Mutex<FlushStats> = Mutex::new(FlushStats::default());
+}
+
+/// Process-wide timing for `CLogger::flush`, collected only while
+/// flush timing is enabled. C sees this as `struct log_flush_stats_rs`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FlushStats {
+    pub count: u64,
+    pub total_ns: u64,
+    pub max_ns: u64,
+}
+
+#[allow(non_camel_case_types)]
+#[doc(hidden)]
+type log_flush_stats_rs = FlushStats;
+
+/// Turns flush timing on or off. It's off by default so flushes don't
+/// pay for two clock reads.
+pub fn set_flush_timing(enabled: bool) {
+    FLUSH_TIMING.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns the flush timings recorded so far.
+pub fn flush_stats() -> FlushStats {
+    *FLUSH_STATS.lock().unwrap()
+}
+
+/// C accessor for `set_flush_timing`.
+#[no_mangle]
+pub extern "C" fn log_set_flush_timing_rs(enabled: bool) {
+    set_flush_timing(enabled);
+}
+
+/// C accessor for `flush_stats`, copies the timings into `out`.
+#[no_mangle]
+pub unsafe extern "C" fn log_get_flush_stats_rs(out: *mut log_flush_stats_rs) -> LoggerStatus {
+    match ptrs::lift_to_option(out) {
+        Some(out) => {
+            *out = flush_stats();
+            LoggerStatus::OK
+        }
+        None => LoggerStatus::NullPointerError,
+    }
+}
+
+fn record_flush(elapsed_ns: u64) {
+    let mut stats = FLUSH_STATS.lock().unwrap();
+    stats.count += 1;
+    stats.total_ns += elapsed_ns;
+    stats.max_ns = cmp::max(stats.max_ns, elapsed_ns);
+}
+
 const DEFAULT_LOG_BASENAME: &str = "ccommon";
 
+/// A user-supplied callback that maps a thread name to a log filename.
+/// It's reference counted so `LogConfigBuilder` can stay `Clone`.
+#[derive(Clone)]
+pub struct FilenameFn(Arc<dyn Fn(&str) -> String + Send + Sync>);
+
+impl fmt::Debug for FilenameFn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("FilenameFn(..)")
+    }
+}
+
+/// A user-supplied formatter. It's handed an empty buffer, writes one
+/// complete line (including the trailing newline) into it, and returns the
+/// number of bytes written.
+#[derive(Clone)]
+pub struct FormatFn(Arc<dyn Fn(&Record, &mut Vec<u8>) -> Result<usize> + Send + Sync>);
+
+impl fmt::Debug for FormatFn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("FormatFn(..)")
+    }
+}
+
 #[repr(C)]
 pub struct LogConfig {
     /// Path to the directory where we will write log files
@@ -256,6 +650,66 @@ pub struct LogConfig {
     buf_size: u32,
 
     level: Level,
+
+    /// If true, any invalid UTF-8 in a formatted line is replaced with
+    /// U+FFFD before it's written, so text log consumers never see bad bytes.
+    enforce_utf8: bool,
+
+    /// When set, every line of a multi-line message after the first is
+    /// written on its own line starting with this prefix (e.g. `"... | "`),
+    /// so the record stays visually grouped under its timestamp.
+    continuation_prefix: Option<String>,
+
+    /// If true, setup installs a panic hook that flushes the panicking
+    /// thread's log before the panic propagates.
+    flush_on_panic: bool,
+
+    /// If set, this line (e.g. version, pid, start time) is written as the
+    /// first line of every log file when it's opened.
+    banner: Option<String>,
+
+    /// If set, called with the thread name to produce the log file's name
+    /// (relative to `path`), replacing the default `prefix.thread.log`.
+    filename_fn: Option<FilenameFn>,
+
+    /// Per-module overrides of `level`, as `(module path prefix, level)`.
+    /// A record uses the level of the longest prefix matching its module
+    /// path, or `level` if none match.
+    module_filters: Vec<(String, Level)>,
+
+    /// If true, each line starts with a per-file sequence number (starting
+    /// at 0) so gaps or reordering can be spotted after the fact.
+    sequence_numbers: bool,
+
+    /// Where line timestamps come from. Defaults to `Clock::Wall`.
+    clock: Clock,
+
+    /// If set, replaces the built-in line layout entirely. The
+    /// `sequence_numbers`, `clock`, `continuation_prefix` and
+    /// `host_and_pid` settings only apply to the built-in layout;
+    /// `enforce_utf8` still applies.
+    format_fn: Option<FormatFn>,
+
+    /// The built-in line layout. Defaults to `LogFormat::Text`.
+    format: LogFormat,
+
+    /// If set, a background thread flushes every thread's log this often,
+    /// so lines from quiet threads don't sit in their buffers indefinitely.
+    flush_interval: Option<time::Duration>,
+
+    /// If set, a thread's log file is rotated once more than this many
+    /// bytes have been written to it: `name.log` becomes `name.1.log`,
+    /// `name.1.log` becomes `name.2.log` and so on.
+    max_file_bytes: Option<u64>,
+
+    /// How many rotated files to keep when `max_file_bytes` is set. The
+    /// oldest is deleted once there are more. Defaults to 5.
+    retained_files: usize,
+
+    /// If set, each line starts with `hostname[pid]`, so logs gathered
+    /// from many hosts can be told apart. Looked up once, when the config
+    /// is built.
+    host_and_pid: Option<(String, u32)>,
 }
 
 #[derive(Clone, Debug)]
@@ -264,6 +718,21 @@ pub struct LogConfigBuilder {
     prefix: Option<String>,
     buf_size: Option<u32>,
     level: Option<Level>,
+    enforce_utf8: Option<bool>,
+    continuation_prefix: Option<String>,
+    flush_on_panic: Option<bool>,
+    banner: Option<String>,
+    filename_fn: Option<FilenameFn>,
+    module_filters: Vec<(String, Level)>,
+    sequence_numbers: Option<bool>,
+    clock: Option<Clock>,
+    format_fn: Option<FormatFn>,
+    format: Option<LogFormat>,
+    flush_interval: Option<time::Duration>,
+    max_file_bytes: Option<u64>,
+    retained_files: Option<usize>,
+    create_dir: Option<bool>,
+    host_and_pid: Option<bool>,
 }
 
 impl Default for LogConfigBuilder {
@@ -272,7 +741,22 @@ impl Default for LogConfigBuilder {
             path: None,
             prefix: Some(String::from("ccommon")),
             buf_size: Some(0),
-            level: Some(Level::Trace)
+            level: Some(Level::Trace),
+            enforce_utf8: Some(false),
+            continuation_prefix: None,
+            flush_on_panic: Some(false),
+            banner: None,
+            filename_fn: None,
+            module_filters: Vec::new(),
+            sequence_numbers: Some(false),
+            clock: Some(Clock::Wall),
+            format_fn: None,
+            format: Some(LogFormat::Text),
+            flush_interval: None,
+            max_file_bytes: None,
+            retained_files: Some(5),
+            create_dir: Some(false),
+            host_and_pid: Some(false),
         }
     }
 }
@@ -303,19 +787,196 @@ impl LogConfigBuilder {
         new
     }
 
+    pub fn enforce_utf8(&mut self, enforce: bool) -> &mut Self {
+        let new = self;
+        new.enforce_utf8 = Some(enforce);
+        new
+    }
+
+    pub fn continuation_prefix(&mut self, prefix: String) -> &mut Self {
+        let new = self;
+        new.continuation_prefix = Some(prefix);
+        new
+    }
+
+    pub fn flush_on_panic(&mut self, flush: bool) -> &mut Self {
+        let new = self;
+        new.flush_on_panic = Some(flush);
+        new
+    }
+
+    pub fn banner(&mut self, banner: String) -> &mut Self {
+        let new = self;
+        new.banner = Some(banner);
+        new
+    }
+
+    pub fn filename_fn<F>(&mut self, f: F) -> &mut Self
+        where F: Fn(&str) -> String + Send + Sync + 'static
+    {
+        let new = self;
+        new.filename_fn = Some(FilenameFn(Arc::new(f)));
+        new
+    }
+
+    /// Adds a level override for `module` and everything under it. Can be
+    /// called repeatedly.
+    pub fn module_filter(&mut self, module: String, lvl: Level) -> &mut Self {
+        let new = self;
+        new.module_filters.push((module, lvl));
+        new
+    }
+
+    pub fn sequence_numbers(&mut self, enabled: bool) -> &mut Self {
+        let new = self;
+        new.sequence_numbers = Some(enabled);
+        new
+    }
+
+    pub fn clock(&mut self, clock: Clock) -> &mut Self {
+        let new = self;
+        new.clock = Some(clock);
+        new
+    }
+
+    pub fn format_fn<F>(&mut self, f: F) -> &mut Self
+        where F: Fn(&Record, &mut Vec<u8>) -> Result<usize> + Send + Sync + 'static
+    {
+        let new = self;
+        new.format_fn = Some(FormatFn(Arc::new(f)));
+        new
+    }
+
+    pub fn format(&mut self, format: LogFormat) -> &mut Self {
+        let new = self;
+        new.format = Some(format);
+        new
+    }
+
+    pub fn flush_interval(&mut self, interval: time::Duration) -> &mut Self {
+        let new = self;
+        new.flush_interval = Some(interval);
+        new
+    }
+
+    pub fn max_file_bytes(&mut self, max: u64) -> &mut Self {
+        let new = self;
+        new.max_file_bytes = Some(max);
+        new
+    }
+
+    pub fn retained_files(&mut self, n: usize) -> &mut Self {
+        let new = self;
+        new.retained_files = Some(n);
+        new
+    }
+
+    pub fn host_and_pid(&mut self, enabled: bool) -> &mut Self {
+        let new = self;
+        new.host_and_pid = Some(enabled);
+        new
+    }
+
+    /// Create the log directory (and any missing parents) in `build` if it
+    /// doesn't exist yet. Off by default.
+    pub fn create_dir(&mut self, create: bool) -> &mut Self {
+        let new = self;
+        new.create_dir = Some(create);
+        new
+    }
+
+    /// Checks that `path` is set and names a writable directory, then
+    /// produces the config.
     pub fn build(&self) -> Result<LogConfig> {
-        if self.path.is_none() {
-            bail!("path field must be set: {:#?}", self)
+        match self.path {
+            Some(ref path) => check_log_dir(path, self.create_dir.unwrap_or(false))?,
+            None => bail!("path field must be set: {:#?}", self),
+        }
+        if let Some(interval) = self.flush_interval {
+            // a zero timeout would have the flusher thread spin
+            if interval <= time::Duration::zero() {
+                bail!("flush_interval must be positive, got {}", interval)
+            }
         }
         Ok(LogConfig{
             path: Clone::clone(&self.path).unwrap().to_owned(),
             prefix: Clone::clone(&self.prefix).unwrap().to_owned(),
             buf_size: Clone::clone(&self.buf_size).unwrap(),
             level: Clone::clone(&self.level).unwrap(),
+            enforce_utf8: Clone::clone(&self.enforce_utf8).unwrap(),
+            continuation_prefix: Clone::clone(&self.continuation_prefix),
+            flush_on_panic: Clone::clone(&self.flush_on_panic).unwrap(),
+            banner: Clone::clone(&self.banner),
+            filename_fn: Clone::clone(&self.filename_fn),
+            module_filters: Clone::clone(&self.module_filters),
+            sequence_numbers: Clone::clone(&self.sequence_numbers).unwrap(),
+            clock: Clone::clone(&self.clock).unwrap(),
+            format_fn: Clone::clone(&self.format_fn),
+            format: Clone::clone(&self.format).unwrap(),
+            flush_interval: Clone::clone(&self.flush_interval),
+            max_file_bytes: Clone::clone(&self.max_file_bytes),
+            retained_files: Clone::clone(&self.retained_files).unwrap(),
+            host_and_pid:
+                if self.host_and_pid.unwrap_or(false) {
+                    Some((hostname(), process::id()))
+                } else {
+                    None
+                },
         })
     }
 }
 
+/// This machine's hostname, or `"unknown"` if it can't be looked up.
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    let rc = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut c_char, buf.len()) };
+    if rc != 0 {
+        return String::from("unknown");
+    }
+    // not guaranteed to be NUL terminated if it was truncated
+    let end = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..end]).into_owned()
+}
+
+fn check_log_dir(path: &str, create: bool) -> ::std::result::Result<(), LoggingError> {
+    let unavailable = |reason: String| {
+        LoggingError::LogDirUnavailable { path: path.to_owned(), reason }
+    };
+    let dir = Path::new(path);
+
+    match fs::metadata(dir) {
+        Ok(ref md) if md.is_dir() => (),
+        Ok(_) => return Err(unavailable("not a directory".to_owned())),
+        Err(ref e) if create && e.kind() == io::ErrorKind::NotFound => {
+            fs::create_dir_all(dir).map_err(|e| unavailable(e.to_string()))?
+        }
+        Err(e) => return Err(unavailable(e.to_string())),
+    }
+
+    // permission bits don't tell the whole story (read-only mounts, acls),
+    // so actually try to create something there
+    tempfile::tempfile_in(dir)
+        .map(|_| ())
+        .map_err(|e| unavailable(format!("not writable: {}", e)))
+}
+
+const ALL_LEVELS: [Level; 5] =
+    [Level::Error, Level::Warn, Level::Info, Level::Debug, Level::Trace];
+
+/// Returns the levels the `log` crate will currently let through, most
+/// severe first. This is derived from `rslog::max_level()`, not stored.
+pub fn effective_levels() -> Vec<Level> {
+    let max = rslog::max_level();
+    ALL_LEVELS.iter().cloned().filter(|l| *l <= max).collect()
+}
+
+/// The most verbose level the `log` crate will currently let through, or
+/// `None` if logging is off. Module filters can raise this above the
+/// configured level.
+pub fn current_level() -> Option<Level> {
+    rslog::max_level().to_level()
+}
+
 fn level_from_usize(u: usize) -> Option<Level> {
     match u {
         1 => Some(Level::Error),
@@ -327,11 +988,23 @@ fn level_from_usize(u: usize) -> Option<Level> {
     }
 }
 
+fn module_matches(module: &str, prefix: &str) -> bool {
+    module.starts_with(prefix) && {
+        let rest = &module[prefix.len()..];
+        rest.is_empty() || rest.starts_with("::")
+    }
+}
+
 impl LogConfig {
+    /// Starts a config from the defaults. Only `path` is required.
+    pub fn builder() -> LogConfigBuilder {
+        LogConfigBuilder::default()
+    }
+
     #[doc(hidden)]
     pub unsafe fn from_raw(ptr: *mut bind::log_config_rs) -> Result<Self> {
-        ptrs::lift_to_option(ptr)
-            .ok_or_else(|| ptrs::NullPointerError.into())
+        ptrs::null_check_named(ptr, "log_config_rs")
+            .map_err(|e| e.into())
             .and_then(|ptr| {
                 let raw = *ptr;
 
@@ -353,26 +1026,94 @@ impl LogConfig {
             })
     }
 
+    /// The level of the longest module filter matching `module`, if any.
+    /// Filters match whole `::` segments, so `a::hot` covers `a::hot` and
+    /// `a::hot::path` but not `a::hotel`.
+    fn module_level(&self, module: &str) -> Option<Level> {
+        self.module_filters.iter()
+            .filter(|&&(ref prefix, _)| module_matches(module, prefix))
+            .max_by_key(|&&(ref prefix, _)| prefix.len())
+            .map(|&(_, lvl)| lvl)
+    }
+
+    /// The most verbose level any record could be logged at, given `base`
+    /// as the default level. This is what the `log` crate's max level needs
+    /// to be set to so that filtered modules aren't cut off early.
+    fn max_level(&self, base: Level) -> Level {
+        self.module_filters.iter()
+            .map(|&(_, lvl)| lvl)
+            .fold(base, cmp::max)
+    }
+
+    /// Where the `n`th most recent rotated copy of `path` goes: the number
+    /// is inserted before a `.log` extension, or appended if there isn't one.
+    fn rotated_path(path: &Path, n: usize) -> PathBuf {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let rotated =
+            if name.ends_with(".log") {
+                format!("{}.{}.log", &name[..name.len() - 4], n)
+            } else {
+                format!("{}.{}", name, n)
+            };
+        path.with_file_name(rotated)
+    }
+
     fn to_path_buf(&self, thread_id: &str) -> PathBuf {
         let mut pb = PathBuf::new();
         pb.push(&self.path);
-        pb.push(format!("{}.{}.log", self.prefix, thread_id));
+        match self.filename_fn {
+            Some(FilenameFn(ref f)) => pb.push(f(thread_id)),
+            None => pb.push(format!("{}.{}.log", self.prefix, thread_id)),
+        }
         pb
     }
 }
 
 
-struct PerThreadLog {
-    /// The underlying cc_log logger instance
+/// A `CLogger` that threads other than its writer may flush. cc_log's
+/// buffer allows one writer and one reader (whoever is flushing) at a
+/// time, so flushes are serialized by `flushing`.
+struct SharedCLogger {
     clogger: CLogger,
+    flushing: Mutex<()>,
+}
+
+impl SharedCLogger {
+    fn new(clogger: CLogger) -> Arc<Self> {
+        Arc::new(SharedCLogger { clogger, flushing: Mutex::new(()) })
+    }
+
+    unsafe fn flush(&self) {
+        let _guard = self.flushing.lock().unwrap();
+        self.clogger.flush();
+    }
+}
+
+unsafe impl Sync for SharedCLogger {}
+unsafe impl Send for SharedCLogger {}
+
+struct PerThreadLog {
+    /// The underlying cc_log logger instance, shared with the `Shim` so
+    /// it can be flushed from other threads
+    clogger: Arc<SharedCLogger>,
     /// The cached thread name or unique identifier
     thread_name: String,
     /// This buffer is used for preparing the message to be logged
     buf: RefCell<Vec<u8>>,
+    /// The config shared with the `Shim` that created us
+    cfg: Arc<LogConfig>,
+    /// The sequence number of the next line, if `cfg.sequence_numbers` is set
+    seq: Cell<u64>,
+    /// `time::precise_time_ns()` when the owning `Shim` was created
+    started_ns: u64,
+    /// The owning `Shim`'s reopen generation when `clogger` was opened
+    generation: usize,
+    /// Bytes written to the current file, for `cfg.max_file_bytes`
+    bytes_written: Cell<u64>,
 }
 
 impl PerThreadLog {
-    fn for_current(cfg: &LogConfig) -> super::Result<Self> {
+    fn for_current(cfg: &Arc<LogConfig>, started_ns: u64, generation: usize) -> super::Result<Self> {
         let tc = thread::current();
         let thread_name =
             tc.name()
@@ -383,9 +1124,71 @@ impl PerThreadLog {
             CLogger::open(cfg.to_path_buf(&thread_name[..]).to_str().unwrap(), cfg.buf_size)?
         };
 
+        if let Some(ref banner) = cfg.banner {
+            unsafe { clogger.write_banner(banner); }
+        }
+
+        let clogger = SharedCLogger::new(clogger);
         let buf = RefCell::new(Vec::with_capacity(PER_THREAD_BUF_SIZE));
 
-        Ok(PerThreadLog{thread_name, clogger, buf})
+        Ok(PerThreadLog{
+            thread_name,
+            clogger,
+            buf,
+            cfg: cfg.clone(),
+            seq: Cell::new(0),
+            started_ns,
+            generation,
+            bytes_written: Cell::new(0),
+        })
+    }
+
+    /// Flushes and closes the current file and opens a new one at the
+    /// configured path. If the new file can't be opened we keep writing
+    /// to the old one.
+    fn reopen(&mut self) -> super::Result<()> {
+        let path = self.cfg.to_path_buf(&self.thread_name[..]);
+        let clogger = unsafe {
+            self.clogger.flush();
+            CLogger::open(path.to_str().unwrap(), self.cfg.buf_size)?
+        };
+
+        if let Some(ref banner) = self.cfg.banner {
+            unsafe { clogger.write_banner(banner); }
+        }
+
+        self.clogger = SharedCLogger::new(clogger);
+        self.bytes_written.set(0);
+        Ok(())
+    }
+
+    fn is_full(&self) -> bool {
+        match self.cfg.max_file_bytes {
+            Some(max) => self.bytes_written.get() > max,
+            None => false,
+        }
+    }
+
+    /// Shifts the existing rotated files up by one, dropping the oldest,
+    /// moves the current file to `.1` and starts a new one.
+    fn rotate(&mut self) -> super::Result<()> {
+        unsafe { self.clogger.flush() };
+
+        let path = self.cfg.to_path_buf(&self.thread_name[..]);
+        let retained = self.cfg.retained_files;
+        if retained == 0 {
+            fs::remove_file(&path)?;
+        } else {
+            for n in (1..retained).rev() {
+                let from = LogConfig::rotated_path(&path, n);
+                if from.exists() {
+                    fs::rename(from, LogConfig::rotated_path(&path, n + 1))?;
+                }
+            }
+            fs::rename(&path, LogConfig::rotated_path(&path, 1))?;
+        }
+
+        self.reopen()
     }
 }
 
@@ -394,6 +1197,7 @@ unsafe impl Send for PerThreadLog {}
 
 
 impl Log for PerThreadLog {
+    // level filtering is done by `Shim::enabled`, before we're looked up
     fn enabled(&self, _: &Metadata) -> bool {
         true
     }
@@ -401,8 +1205,43 @@ impl Log for PerThreadLog {
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
             let mut buf = self.buf.borrow_mut();
-            let sz = format(record, &mut buf).unwrap();
-            unsafe { self.clogger.write(&buf[0..sz]); }
+            let seq =
+                if self.cfg.sequence_numbers {
+                    let n = self.seq.get();
+                    self.seq.set(n + 1);
+                    Some(n)
+                } else {
+                    None
+                };
+            let elapsed_ns =
+                match self.cfg.clock {
+                    Clock::Monotonic => time::precise_time_ns() - self.started_ns,
+                    Clock::Wall | Clock::Local => 0,
+                };
+            let line = LineInfo { seq, elapsed_ns, now: None };
+            let formatted =
+                match self.cfg.format_fn {
+                    Some(FormatFn(ref f)) => {
+                        buf.clear();
+                        f(record, &mut buf)
+                    }
+                    None => format(record, &mut buf, &self.cfg, &line),
+                };
+            // a line we can't format is dropped, it mustn't take the thread down
+            let mut sz =
+                match formatted {
+                    Ok(sz) => sz,
+                    Err(err) => {
+                        limited_eprintln!("failed to format log record, dropping it: {}", err);
+                        return;
+                    }
+                };
+            if self.cfg.enforce_utf8 {
+                sz = enforce_utf8(&mut buf, sz);
+            }
+            if unsafe { self.clogger.clogger.write(&buf[0..sz]) } {
+                self.bytes_written.set(self.bytes_written.get() + sz as u64);
+            }
         }
     }
 
@@ -416,27 +1255,168 @@ impl Log for PerThreadLog {
 /// the thread local loggers.
 struct Shim {
     tls: CachedThreadLocal<RefCell<Option<PerThreadLog>>>,
-    cfg: LogConfig,
+    cfg: Arc<LogConfig>,
+    /// The current level, starts out as `cfg.level` but can be changed at runtime
+    level: AtomicUsize,
+    /// When this logger was set up, the zero point for `Clock::Monotonic`
+    started_ns: u64,
+    /// Bumped by `Handle::reopen`. Each thread reopens its file the next
+    /// time it logs or flushes and sees its `PerThreadLog` is behind.
+    generation: AtomicUsize,
+    /// Every thread's current logger, so they can all be flushed from one
+    /// place. Loggers replaced by a reopen drop out on their own.
+    loggers: Mutex<Vec<Weak<SharedCLogger>>>,
+    /// For threads whose file couldn't be opened, the `generation` at the
+    /// time. A reopen request retries the open.
+    failed_opens: CachedThreadLocal<Cell<usize>>,
+    /// Which `log_setup_safe` call installed this shim, see `Handle::active`
+    setup_id: usize,
 }
 
 impl Shim {
+    /// Returns this thread's logger, opening it on first use. If the open
+    /// fails, the error is returned that one time and the thread is left
+    /// with `None`, so later calls are no-ops rather than retrying the open
+    /// on every line. `Handle::reopen` gets it another try.
     fn get_per_thread(&self) -> super::Result<&RefCell<Option<PerThreadLog>>> {
-        self.tls.get_or_try(||
-            PerThreadLog::for_current(&self.cfg)
-                .map(|ptl| Box::new(RefCell::new(Some(ptl))) )
-        )
+        let mut open_err = None;
+        let cell = self.tls.get_or(|| {
+            let generation = self.generation.load(Ordering::Acquire);
+            match PerThreadLog::for_current(&self.cfg, self.started_ns, generation) {
+                Ok(ptl) => {
+                    self.register(&ptl.clogger);
+                    Box::new(RefCell::new(Some(ptl)))
+                }
+                Err(err) => {
+                    open_err = Some(err);
+                    self.failed_opens.get_or(|| Box::new(Cell::new(0))).set(generation);
+                    Box::new(RefCell::new(None))
+                }
+            }
+        });
+
+        match open_err {
+            Some(err) => Err(err),
+            None => Ok(cell),
+        }
     }
 
-    fn new(cfg: LogConfig) -> Self {
-        Shim { cfg, tls: CachedThreadLocal::new() }
+    fn register(&self, clogger: &Arc<SharedCLogger>) {
+        let mut loggers = self.loggers.lock().unwrap();
+        loggers.retain(|w| w.upgrade().is_some());
+        loggers.push(Arc::downgrade(clogger));
     }
 
-    fn shutdown(&mut self) {
-        for cell in self.tls.iter_mut() {
-            if let Some(ptl) = cell.replace(None) {
-                ptl.flush();
-                drop(ptl);
-            }
+    /// Flushes every thread's logger. This can run while those threads are
+    /// logging: a flush racing a write just leaves the newest lines for
+    /// the next flush.
+    fn flush_all(&self) {
+        let loggers: Vec<Arc<SharedCLogger>> =
+            self.loggers.lock().unwrap()
+                .iter()
+                .filter_map(|w| w.upgrade())
+                .collect();
+
+        for clogger in loggers {
+            unsafe { clogger.flush() };
+        }
+    }
+
+    /// Flushes the calling thread's logger if it has one. Unlike
+    /// `Log::flush`, this never opens a file for a thread that hasn't
+    /// logged anything.
+    fn flush_current_thread(&self) {
+        if let Some(cell) = self.tls.get() {
+            if let Ok(slot) = cell.try_borrow() {
+                if let Some(ptl) = &*slot {
+                    ptl.flush();
+                }
+            }
+        }
+    }
+
+    fn new(cfg: LogConfig, setup_id: usize) -> Self {
+        let level = AtomicUsize::new(cfg.level as usize);
+        let started_ns = time::precise_time_ns();
+        Shim {
+            cfg: Arc::new(cfg),
+            tls: CachedThreadLocal::new(),
+            level,
+            started_ns,
+            generation: AtomicUsize::new(0),
+            loggers: Mutex::new(Vec::new()),
+            failed_opens: CachedThreadLocal::new(),
+            setup_id,
+        }
+    }
+
+    fn level(&self) -> Level {
+        level_from_usize(self.level.load(Ordering::Relaxed)).unwrap()
+    }
+
+    fn set_level(&self, level: Level) {
+        self.level.store(level as usize, Ordering::Relaxed);
+    }
+
+    fn shutdown(&mut self) {
+        for cell in self.tls.iter_mut() {
+            if let Some(ptl) = cell.replace(None) {
+                ptl.flush();
+                drop(ptl);
+            }
+        }
+    }
+
+    fn request_reopen(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Reopens this thread's file if a reopen was requested since it was
+    /// last opened. Skipped if the log is already borrowed further up the
+    /// stack, in which case the next call will pick it up.
+    fn reopen_if_stale(&self, cell: &RefCell<Option<PerThreadLog>>) {
+        let generation = self.generation.load(Ordering::Acquire);
+        if let Ok(mut slot) = cell.try_borrow_mut() {
+            if let Some(ptl) = slot.as_mut() {
+                if ptl.generation != generation {
+                    ptl.generation = generation;
+                    match ptl.reopen() {
+                        Ok(()) => self.register(&ptl.clogger),
+                        Err(err) => limited_eprintln!("failed to reopen log file: {}", err),
+                    }
+                }
+                return;
+            }
+
+            // the first open failed, try again now that a reopen was asked for
+            if let Some(failed) = self.failed_opens.get() {
+                if failed.get() != generation {
+                    failed.set(generation);
+                    match PerThreadLog::for_current(&self.cfg, self.started_ns, generation) {
+                        Ok(ptl) => {
+                            self.register(&ptl.clogger);
+                            *slot = Some(ptl);
+                        }
+                        Err(err) => limited_eprintln!("failed to reopen log file: {}", err),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Rotates this thread's file if it's grown past `max_file_bytes`.
+    fn rotate_if_full(&self, cell: &RefCell<Option<PerThreadLog>>) {
+        if let Ok(mut slot) = cell.try_borrow_mut() {
+            if let Some(ptl) = slot.as_mut() {
+                if ptl.is_full() {
+                    // don't retry on every line if rotating keeps failing
+                    ptl.bytes_written.set(0);
+                    match ptl.rotate() {
+                        Ok(()) => self.register(&ptl.clogger),
+                        Err(err) => limited_eprintln!("failed to rotate log file: {}", err),
+                    }
+                }
+            }
         }
     }
 
@@ -446,28 +1426,38 @@ impl Shim {
     {
         self.get_per_thread()
             .map(|cell| {
+                self.reopen_if_stale(cell);
                 if let Some(ptl) = &*cell.borrow() {
                     f(ptl);
                 }
+                self.rotate_if_full(cell);
             })
             .err()
     }
 }
 
 impl Log for Shim {
-    fn enabled(&self, _: &Metadata) -> bool {
-        true
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let level =
+            self.cfg.module_level(metadata.target())
+                .unwrap_or_else(|| self.level());
+        metadata.level() <= level
     }
 
     fn log(&self, record: &Record) {
+        // the log crate only checks the global max level, which may be more
+        // verbose than this record's module allows
+        if !self.enabled(record.metadata()) {
+            return;
+        }
         if let Some(err) = self.borrow_and_call(|ptl| ptl.log(record)) {
-            eprintln!("err in Shim::log {:#?}", err);
+            limited_eprintln!("err in Shim::log {:#?}", err);
         }
     }
 
     fn flush(&self) {
         if let Some(err) = self.borrow_and_call(|ptl| ptl.flush()) {
-            eprintln!("err in Shim::flush {:#?}", err);
+            limited_eprintln!("err in Shim::flush {:#?}", err);
         }
     }
 }
@@ -535,7 +1525,46 @@ impl Log for Logger {
 /// shutting down the per-thread loggers in the `Shim`.
 #[repr(C)]
 pub struct Handle {
-    shim: Arc<ArcCell<Option<Shim>>>
+    shim: Arc<ArcCell<Option<Shim>>>,
+    flusher: Option<Flusher>,
+    /// Matches the `setup_id` of the `Shim` this handle installed. The cell
+    /// is shared by every setup, so this is how a handle kept around after
+    /// its logger was shut down tells it apart from a later one.
+    setup_id: usize,
+}
+
+/// The background thread started when `flush_interval` is set. It stops
+/// when `stop` is dropped.
+struct Flusher {
+    stop: mpsc::Sender<()>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl Flusher {
+    fn spawn(shim: Arc<ArcCell<Option<Shim>>>, interval: time::Duration) -> Result<Self> {
+        let interval = interval.to_std()?;
+        let (stop, stop_rx) = mpsc::channel::<()>();
+
+        let thread =
+            thread::Builder::new()
+                .name(String::from("ccommon-log-flusher"))
+                .spawn(move || {
+                    while let Err(mpsc::RecvTimeoutError::Timeout) = stop_rx.recv_timeout(interval) {
+                        if let Some(shim) = &*shim.get() {
+                            shim.flush_all();
+                        }
+                    }
+                })?;
+
+        Ok(Flusher { stop, thread })
+    }
+
+    fn stop(self) {
+        drop(self.stop);
+        if self.thread.join().is_err() {
+            eprintln!("log flusher thread panicked");
+        }
+    }
 }
 
 #[allow(non_camel_case_types)]
@@ -543,37 +1572,127 @@ pub struct Handle {
 type log_handle_rs = Handle;
 
 impl Handle {
-    fn shutdown(&mut self, timeout: time::Duration) {
-        let mut active: Arc<Option<Shim>> = self.shim.set(Arc::new(None));
+    /// Stops logging and closes every thread's log file, waiting up to
+    /// `timeout` for threads that are mid-write. Dropping the handle does
+    /// the same with a default timeout.
+    pub fn shutdown(&mut self, timeout: time::Duration) {
+        // stop the flusher first, it holds a reference to the shim while flushing
+        if let Some(flusher) = self.flusher.take() {
+            flusher.stop();
+        }
 
-        let stop_at = time::SteadyTime::now() + timeout;
+        let mut active: Arc<Option<Shim>> = {
+            // hold the lock so a new setup can't go live between the check
+            // and the swap, we'd shut down its logger instead of ours
+            let _installed = INSTALLED.lock().unwrap_or_else(|p| p.into_inner());
+            if self.active().is_none() {
+                // we've already shut down
+                eprintln!("already shut down!");
+                return;
+            }
+            self.shim.set(Arc::new(None))
+        };
 
-        if active.is_none() {
-            // we've already shut down
-            eprintln!("already shut down!");
-            return;
-        }
+        let stop_at = time::SteadyTime::now() + timeout;
 
+        // threads that were mid-log when we swapped the shim out still hold
+        // a reference, keep trying until they've let go or we run out of time
+        let mut backoff_us = SHUTDOWN_BACKOFF_START_US;
         loop {
-            if let Some(opt_shim) = Arc::get_mut(&mut active) {
-                if let Some(shim) = opt_shim {
+            match Arc::get_mut(&mut active) {
+                Some(Some(shim)) => {
                     shim.shutdown();
-                    break
+                    return
                 }
-            } else {
-                eprintln!("failed to get_mut on the active logger");
-                thread::yield_now();
+                Some(None) => return,
+                None => (),
             }
 
-            if time::SteadyTime::now() < stop_at {
+            let now = time::SteadyTime::now();
+            if now >= stop_at {
                 eprintln!("timed out waiting on log shutdown, best of luck!");
-                break
+                return
             }
+
+            limited_eprintln!("failed to get_mut on the active logger");
+            let remaining_us = (stop_at - now).num_microseconds().unwrap_or(i64::max_value()) as u64;
+            thread::sleep(::std::time::Duration::from_micros(cmp::min(backoff_us, remaining_us)));
+            backoff_us = cmp::min(backoff_us * 2, SHUTDOWN_BACKOFF_CAP_MS * 1000);
         }
     }
 
+    /// The `Shim` this handle installed, or `None` once it's been shut
+    /// down, even if a later setup has installed another one since.
+    fn active(&self) -> Arc<Option<Shim>> {
+        let active = self.shim.get();
+        let ours =
+            match *active {
+                Some(ref shim) => shim.setup_id == self.setup_id,
+                None => false,
+            };
+
+        if ours { active } else { Arc::new(None) }
+    }
+
     fn is_setup(&self) -> bool {
-        self.shim.get().is_some()
+        self.active().is_some()
+    }
+
+    /// Returns the levels that would currently be written by this logger
+    /// for modules without a filter, taking both `rslog::max_level()` and
+    /// the logger's own level into account. Empty once the logger has been shut down.
+    pub fn effective_levels(&self) -> Vec<Level> {
+        match &*self.active() {
+            Some(shim) => {
+                let level = shim.level();
+                effective_levels().into_iter().filter(|l| *l <= level).collect()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Asks every thread to close its log file and open a fresh one at the
+    /// configured path, e.g. after logrotate has renamed the old file away.
+    ///
+    /// This is best-effort and lazy: each thread reopens on its own next
+    /// log or flush call, so a thread that never logs again keeps the old
+    /// file open until shutdown. Returns false if logging has already been
+    /// shut down.
+    pub fn reopen(&self) -> bool {
+        match &*self.active() {
+            Some(shim) => {
+                shim.request_reopen();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Flushes every thread's logger from the calling thread, e.g. at a
+    /// checkpoint before a crash dump. Best-effort: threads logging at the
+    /// same time may have their newest lines left for the next flush.
+    /// Returns false if logging has already been shut down.
+    pub fn flush_all(&self) -> bool {
+        match &*self.active() {
+            Some(shim) => {
+                shim.flush_all();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Changes the level of the active logger, returning false if
+    /// logging has already been shut down.
+    fn set_level(&self, level: Level) -> bool {
+        match &*self.active() {
+            Some(shim) => {
+                shim.set_level(level);
+                rslog::set_max_level(shim.cfg.max_level(level).to_level_filter());
+                true
+            }
+            None => false,
+        }
     }
 }
 
@@ -584,29 +1703,176 @@ pub unsafe extern "C" fn log_is_setup_rs(cfgp: *mut Handle) -> bool {
         .expect("log_is_setup_rs was passed a raw pointer")
 }
 
+/// Returns true if the logger referred to by `ph` currently has a live
+/// `Shim` installed, false if it has been shut down. Unlike
+/// `log_is_setup_rs` this accepts NULL, returning false.
+#[no_mangle]
+pub unsafe extern "C" fn log_is_active_rs(ph: *mut Handle) -> bool {
+    ptrs::lift_to_option(ph)
+        .map(|p| (*p).is_setup())
+        .unwrap_or(false)
+}
+
+/// Changes the level of the logger referred to by `ph`. `level` is one of
+/// the `log_level_rs_e` values.
+#[no_mangle]
+pub unsafe extern "C" fn log_set_level_rs(ph: *mut Handle, level: u32) -> LoggerStatus {
+    let handle =
+        match ptrs::lift_to_option(ph) {
+            Some(ph) => &*ph,
+            None => return LoggerStatus::NullPointerError,
+        };
+
+    let level =
+        match level_from_usize(level as usize) {
+            Some(level) => level,
+            None => return LoggerStatus::InvalidLevel,
+        };
+
+    if handle.set_level(level) {
+        LoggerStatus::OK
+    } else {
+        LoggerStatus::LoggerNotSetupError
+    }
+}
+
+/// Returns the most verbose level that will currently be logged, as a
+/// `log_level_rs_e`, or 0 if logging is off. Lets C skip building messages
+/// nobody will see.
+#[no_mangle]
+pub extern "C" fn log_get_level_rs() -> u32 {
+    current_level().map(|l| l as u32).unwrap_or(0)
+}
+
+/// Asks the logger referred to by `ph` to reopen its files, for use from a
+/// SIGHUP handler after log rotation. See `Handle::reopen`; each thread
+/// reopens lazily on its next write.
+#[no_mangle]
+pub unsafe extern "C" fn log_reopen_rs(ph: *mut Handle) -> LoggerStatus {
+    match ptrs::lift_to_option(ph) {
+        Some(ph) if (*ph).reopen() => LoggerStatus::OK,
+        Some(_) => LoggerStatus::LoggerNotSetupError,
+        None => LoggerStatus::NullPointerError,
+    }
+}
+
+/// Flushes every thread's log buffer. See `Handle::flush_all`.
+#[no_mangle]
+pub unsafe extern "C" fn log_flush_all_rs(ph: *mut Handle) -> LoggerStatus {
+    match ptrs::lift_to_option(ph) {
+        Some(ph) if (*ph).flush_all() => LoggerStatus::OK,
+        Some(_) => LoggerStatus::LoggerNotSetupError,
+        None => LoggerStatus::NullPointerError,
+    }
+}
+
 const SHUTDOWN_TIMEOUT_MS: u64 = 1000;
 
+/// How long shutdown first sleeps when another thread still holds the
+/// logger. Doubles on each retry up to `SHUTDOWN_BACKOFF_CAP_MS`.
+const SHUTDOWN_BACKOFF_START_US: u64 = 50;
+
+/// The longest shutdown sleeps between attempts to take the logger back.
+pub const SHUTDOWN_BACKOFF_CAP_MS: u64 = 10;
+
 impl Drop for Handle {
     fn drop(&mut self) {
         self.shutdown(time::Duration::milliseconds(SHUTDOWN_TIMEOUT_MS as i64));
     }
 }
 
-fn log_setup_safe(config: LogConfig) -> Result<Handle> {
-    rslog::set_max_level(config.level.to_level_filter());
-    let shim = Shim::new(config);
-    let logger = Logger(Arc::new(ArcCell::new(Arc::new(Some(shim)))));
+static FLUSH_ON_PANIC_HOOK: Once = ONCE_INIT;
+
+/// Chains a panic hook in front of whatever hook is currently installed
+/// that flushes the panicking thread's logger, so the lines leading up to
+/// the panic make it to disk. The hook looks up the currently installed
+/// `Shim`, so it keeps working across re-setups and is only installed once.
+fn install_flush_on_panic_hook() {
+    FLUSH_ON_PANIC_HOOK.call_once(|| {
+        let prev = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            // try_lock: the panic may have happened while setup held the
+            // lock. A poisoned lock still holds a usable cell, and giving up
+            // on it would turn flush-on-panic off for good.
+            let installed =
+                match INSTALLED.try_lock() {
+                    Ok(installed) => Some(installed),
+                    Err(TryLockError::Poisoned(p)) => Some(p.into_inner()),
+                    Err(TryLockError::WouldBlock) => None,
+                };
+            if let Some(installed) = installed {
+                if let Some(ref cell) = *installed {
+                    if let Some(shim) = &*cell.get() {
+                        shim.flush_current_thread();
+                    }
+                }
+            }
+            prev(info);
+        }));
+    });
+}
 
-    let handle = Handle {shim: logger.0.clone()};
+lazy_static! {
+    /// The cell shared with the `Logger` we registered with the log crate.
+    /// The log crate only lets us register once per process, so after a
+    /// shutdown a new setup installs its `Shim` here instead.
+    static ref INSTALLED: Mutex<Option<Arc<ArcCell<Option<Shim>>>>> = Mutex::new(None);
+}
 
-    rslog::set_boxed_logger(Box::new(logger))
-        .map(|()| handle)
-        .map_err(|e| e.into())
+/// Handed out to each setup in turn, only bumped while `INSTALLED` is held.
+static SETUP_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Installs the logger described by `config` with the `log` crate. Logging
+/// stays set up until the returned `Handle` is shut down or dropped.
+pub fn log_setup_safe(config: LogConfig) -> Result<Handle> {
+    let mut installed = INSTALLED.lock().unwrap();
+
+    if let Some(ref cell) = *installed {
+        if cell.get().is_some() {
+            return Err(LoggingError::LoggingAlreadySetUp.into());
+        }
+    }
+
+    let cell =
+        match *installed {
+            // we've been set up and shut down before, reuse the registration
+            Some(ref cell) => cell.clone(),
+            None => Arc::new(ArcCell::new(Arc::new(None))),
+        };
+
+    // everything that can fail happens before the shim goes live, so an
+    // error never leaves behind a logger nobody holds a Handle for
+    let flusher =
+        match config.flush_interval {
+            Some(interval) => Some(Flusher::spawn(cell.clone(), interval)?),
+            None => None,
+        };
+
+    if installed.is_none() {
+        if let Err(e) = rslog::set_boxed_logger(Box::new(Logger(cell.clone()))) {
+            if let Some(flusher) = flusher {
+                flusher.stop();
+            }
+            return Err(e.into());
+        }
+    }
+
+    rslog::set_max_level(config.max_level(config.level).to_level_filter());
+    let flush_on_panic = config.flush_on_panic;
+    let setup_id = SETUP_ID.fetch_add(1, Ordering::Relaxed);
+    cell.set(Arc::new(Some(Shim::new(config, setup_id))));
+    *installed = Some(cell.clone());
+
+    if flush_on_panic {
+        install_flush_on_panic_hook();
+    }
+
+    Ok(Handle {shim: cell, flusher, setup_id})
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn log_create_handle_rs(cfgp: *mut bind::log_config_rs) -> *mut Handle {
-    ptrs::null_check(cfgp)                                // make sure our input is good
+    ptrs::null_check_named(cfgp, "log_config_rs")         // make sure our input is good
         .map_err(|e| e.into())                            // error type bookkeeping
         .and_then(|c|LogConfig::from_raw(c))              // convert the *mut into a rust struct
         .and_then(log_setup_safe)                         // register our logger
@@ -619,13 +1885,14 @@ pub unsafe extern "C" fn log_create_handle_rs(cfgp: *mut bind::log_config_rs) ->
 
 #[no_mangle]
 pub unsafe extern "C" fn log_shutdown_rs(ph: *mut Handle, timeout_ms: u32) -> LoggerStatus {
-    let mut handle =
+    // the handle stays owned by the caller, who frees it with log_destroy_handle_rs
+    let handle =
         match ptrs::lift_to_option(ph) {
-            Some(ph) => Box::from_raw(ph),
+            Some(ph) => &mut *ph,
             None => return LoggerStatus::NullPointerError,
         };
 
-    Handle::shutdown(&mut handle, time::Duration::milliseconds(timeout_ms as i64));
+    Handle::shutdown(handle, time::Duration::milliseconds(timeout_ms as i64));
 
     LoggerStatus::OK
 }
@@ -638,6 +1905,129 @@ pub unsafe extern "C" fn log_destroy_handle_rs(pph: *mut *mut Handle) {
     *pph = ptr::null_mut();
 }
 
+// C-facing config builder
+//
+// Ownership rules: every `const char *` passed in stays owned by the caller
+// and is copied before the call returns, so it may be freed right after.
+// The builder itself is owned by Rust; C holds it as an opaque pointer and
+// must release it with `log_config_builder_destroy_rs` exactly once. A
+// handle created from a builder does not borrow from it, so the builder
+// may be destroyed while the handle is still in use.
+
+unsafe fn copy_c_str(s: *const c_char) -> ::std::result::Result<String, LoggerStatus> {
+    if s.is_null() {
+        return Err(LoggerStatus::NullPointerError);
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map(|s| s.to_owned())
+        .map_err(|_| LoggerStatus::InvalidUTF8)
+}
+
+/// Allocates a builder with default settings. Free it with
+/// `log_config_builder_destroy_rs`.
+#[no_mangle]
+pub extern "C" fn log_config_builder_create_rs() -> *mut LogConfigBuilder {
+    Box::into_raw(Box::new(LogConfigBuilder::default()))
+}
+
+/// Frees the builder pointed to by `*pb` and sets `*pb` to NULL.
+#[no_mangle]
+pub unsafe extern "C" fn log_config_builder_destroy_rs(pb: *mut *mut LogConfigBuilder) {
+    assert!(!pb.is_null());
+    if !(*pb).is_null() {
+        drop(Box::from_raw(*pb));
+        *pb = ptr::null_mut();
+    }
+}
+
+/// Sets the log directory. `path` is copied.
+#[no_mangle]
+pub unsafe extern "C" fn log_config_builder_path_rs(
+    b: *mut LogConfigBuilder,
+    path: *const c_char
+) -> LoggerStatus {
+    if b.is_null() {
+        return LoggerStatus::NullPointerError;
+    }
+    match copy_c_str(path) {
+        Ok(path) => { (*b).path(path); LoggerStatus::OK }
+        Err(status) => status,
+    }
+}
+
+/// Sets the log filename prefix. `prefix` is copied.
+#[no_mangle]
+pub unsafe extern "C" fn log_config_builder_prefix_rs(
+    b: *mut LogConfigBuilder,
+    prefix: *const c_char
+) -> LoggerStatus {
+    if b.is_null() {
+        return LoggerStatus::NullPointerError;
+    }
+    match copy_c_str(prefix) {
+        Ok(prefix) => { (*b).prefix(prefix); LoggerStatus::OK }
+        Err(status) => status,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn log_config_builder_buf_size_rs(
+    b: *mut LogConfigBuilder,
+    buf_size: u32
+) -> LoggerStatus {
+    match ptrs::lift_to_option(b) {
+        Some(b) => { (*b).buf_size(buf_size); LoggerStatus::OK }
+        None => LoggerStatus::NullPointerError,
+    }
+}
+
+/// Whether to create the log directory if it's missing.
+#[no_mangle]
+pub unsafe extern "C" fn log_config_builder_create_dir_rs(
+    b: *mut LogConfigBuilder,
+    create: bool
+) -> LoggerStatus {
+    match ptrs::lift_to_option(b) {
+        Some(b) => { (*b).create_dir(create); LoggerStatus::OK }
+        None => LoggerStatus::NullPointerError,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn log_config_builder_level_rs(
+    b: *mut LogConfigBuilder,
+    level: u32
+) -> LoggerStatus {
+    let b =
+        match ptrs::lift_to_option(b) {
+            Some(b) => &mut *b,
+            None => return LoggerStatus::NullPointerError,
+        };
+
+    match level_from_usize(level as usize) {
+        Some(level) => { b.level(level); LoggerStatus::OK }
+        None => LoggerStatus::InvalidLevel,
+    }
+}
+
+/// Like `log_create_handle_rs`, but configured from a builder. The builder
+/// is not consumed. Returns NULL on error.
+#[no_mangle]
+pub unsafe extern "C" fn log_create_handle_from_builder_rs(
+    b: *const LogConfigBuilder
+) -> *mut Handle {
+    ptrs::null_check_named(b as *mut LogConfigBuilder, "log_config_builder_rs")
+        .map_err(|e| e.into())
+        .and_then(|b| (*b).build())
+        .and_then(log_setup_safe)
+        .map(|handle| Box::into_raw(Box::new(handle)))
+        .unwrap_or_else(|err| {
+            eprintln!("ERROR log_create_handle_from_builder: {:#?}", err);
+            ptr::null_mut()
+        })
+}
+
 // for integration testing with C
 #[doc(hidden)]
 #[no_mangle]
@@ -665,6 +2055,7 @@ mod test {
     use std::fs;
     use std::sync::mpsc;
     use super::*;
+    use super::harness::{self, LogTestHarness, MtLogDir};
     use tempfile;
     use time;
 
@@ -680,18 +2071,38 @@ mod test {
         }
     }
 
+    fn log_metrics_lifecycle() {
+        assert_result(|| {
+            let mut metrics = LogMetrics::new();
+            unsafe { bind::log_setup(metrics.as_mut_ptr()) };
+
+            let h = LogTestHarness::open(0)?;
+            assert!(h.write(b"counted\n"));
+            assert_eq!(h.read_all()?, "counted\n");
+
+            // loggers first, then cc_log, then the metrics it points at
+            drop(h);
+            unsafe { bind::log_teardown() };
+            drop(metrics);
+
+            Ok(())
+        })
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_log_metrics_lifecycle() { log_metrics_lifecycle(); }
+    }
+
     fn basic_mt_roundtrip() {
         assert_result(|| {
-            let mut stats = LogMetrics::new();
-            unsafe { bind::log_setup(stats.as_mut_ptr()) };
+            ensure_cc_log_setup();
             let tmpdir = tempfile::tempdir()?;
 
-            let cfg = LogConfig {
-                path: tmpdir.path().to_path_buf().to_str().unwrap().to_owned(),
-                prefix: String::from("testmt"),
-                buf_size: 0,
-                level: Level::Trace,
-            };
+            let cfg = LogConfigBuilder::default()
+                .path(tmpdir.path().to_path_buf().to_str().unwrap().to_owned())
+                .prefix(String::from("testmt"))
+                .build()?;
 
             let handle = log_setup_safe(cfg).unwrap();
 
@@ -719,16 +2130,13 @@ mod test {
 
     fn named_threads_test() {
         assert_result(||{
-            let mut stats = LogMetrics::new();
-            unsafe { bind::log_setup(stats.as_mut_ptr()) };
+            ensure_cc_log_setup();
             let tmpdir = tempfile::tempdir()?;
 
-            let cfg = LogConfig {
-                path: tmpdir.path().to_path_buf().to_str().unwrap().to_owned(),
-                prefix: String::from("testmt"),
-                buf_size: 0,
-                level: Level::Trace,
-            };
+            let cfg = LogConfigBuilder::default()
+                .path(tmpdir.path().to_path_buf().to_str().unwrap().to_owned())
+                .prefix(String::from("testmt"))
+                .build()?;
 
             let handle = log_setup_safe(cfg).unwrap();
 
@@ -766,16 +2174,13 @@ mod test {
     fn mt_shutdown_resilience_test() {
         assert_result(||{
             // make sure a thread logging doesn't crash if we shutdown simultaneously
-            let mut stats = LogMetrics::new();
-            unsafe { bind::log_setup(stats.as_mut_ptr()) };
+            ensure_cc_log_setup();
             let tmpdir = tempfile::tempdir()?;
 
-            let cfg = LogConfig {
-                path: tmpdir.path().to_path_buf().to_str().unwrap().to_owned(),
-                prefix: String::from("testmt"),
-                buf_size: 0,
-                level: Level::Trace,
-            };
+            let cfg = LogConfigBuilder::default()
+                .path(tmpdir.path().to_path_buf().to_str().unwrap().to_owned())
+                .prefix(String::from("testmt"))
+                .build()?;
 
             let handle = log_setup_safe(cfg).unwrap();
 
@@ -837,20 +2242,1482 @@ mod test {
         })
     }
 
-    // runs this test with process isolation
+    #[test]
+    fn test_enforce_utf8_replaces_invalid_bytes() {
+        let mut buf = b"bad \xff\xfe bytes\n".to_vec();
+        let sz = buf.len();
+
+        let sz = enforce_utf8(&mut buf, sz);
+        assert_eq!(
+            str::from_utf8(&buf[..sz]).unwrap(),
+            "bad \u{FFFD}\u{FFFD} bytes\n"
+        );
+    }
+
+    #[test]
+    fn test_enforce_utf8_leaves_valid_lines_alone() {
+        let mut buf = "all good ✓\n".as_bytes().to_vec();
+        buf.extend_from_slice(b"trailing junk past sz \xff");
+        let sz = "all good ✓\n".len();
+
+        assert_eq!(enforce_utf8(&mut buf, sz), sz);
+        assert_eq!(&buf[..sz], "all good ✓\n".as_bytes());
+    }
+
+    fn enforce_utf8_end_to_end_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+
+            let mut cfg = dir.config("ccommon");
+            cfg.format_fn(|record, buf| {
+                    write!(buf, "{} ", record.args())?;
+                    buf.extend_from_slice(b"\xff\n");
+                    Ok(buf.len())
+                })
+                .enforce_utf8(true);
+
+            let s = dir.run(&cfg, "utf8", || {
+                info!("raw");
+            })?;
+
+            // reading it back as a String would fail if the 0xff had made it to disk
+            assert_eq!(s, "raw \u{FFFD}\n");
+
+            Ok(())
+        })
+    }
+
     rusty_fork_test! {
         #[test]
-        fn test_basic_mt_roundtrip() { basic_mt_roundtrip(); }
+        fn test_enforce_utf8_end_to_end() { enforce_utf8_end_to_end_test(); }
+    }
+
+    #[test]
+    fn test_format_timestamp_microseconds() {
+        assert_result(|| {
+            let cfg = harness::config().build()?;
+
+            let format_at = |nsec: i32| -> Result<String> {
+                let line = LineInfo {
+                    now: Some(time::at_utc(time::Timespec::new(1_500_000_000, nsec))),
+                    ..LineInfo::default()
+                };
+                harness::format_line(
+                    &cfg,
+                    &Record::builder().args(format_args!("tick")).level(Level::Info).build(),
+                    &line,
+                )
+            };
+
+            let out = format_at(500_000_000)?;
+            assert!(out.starts_with("2017-07-14 02:40:00.500000 INFO "), "{}", out);
+
+            let out = format_at(999_999_999)?;
+            assert!(out.starts_with("2017-07-14 02:40:00.999999 INFO "), "{}", out);
+
+            let out = format_at(1_000)?;
+            assert!(out.starts_with("2017-07-14 02:40:00.000001 INFO "), "{}", out);
+
+            Ok(())
+        })
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn test_format_key_values() {
+        assert_result(|| {
+            let cfg = harness::config().build()?;
+
+            let out = harness::format_line(
+                &cfg,
+                &Record::builder()
+                    .args(format_args!("login failed"))
+                    .level(Level::Warn)
+                    .key_values(&[
+                        ("user", "jane doe"),
+                        ("attempts", "3"),
+                        ("query", "a=b"),
+                        ("note", ""),
+                    ])
+                    .build(),
+                &LineInfo::default(),
+            )?;
+
+            assert!(
+                out.ends_with("login failed attempts=3 note=\"\" query=\"a=b\" user=\"jane doe\"\n"),
+                "{}", out
+            );
+
+            Ok(())
+        })
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn test_format_json_key_values() {
+        assert_result(|| {
+            let cfg = harness::config().format(LogFormat::Json).build()?;
+
+            let out = harness::format_line(
+                &cfg,
+                &Record::builder()
+                    .args(format_args!("evicted"))
+                    .level(Level::Info)
+                    .key_values(&[("slab", "12"), ("reason", "lru \"cold\"")])
+                    .build(),
+                &LineInfo::default(),
+            )?;
+
+            let v: serde_json::Value = serde_json::from_str(&out)?;
+            assert_eq!(v["msg"], "evicted");
+            assert_eq!(v["kv"]["slab"], "12");
+            assert_eq!(v["kv"]["reason"], "lru \"cold\"");
+
+            Ok(())
+        })
+    }
+
+    #[cfg(feature = "kv")]
+    fn key_values_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+
+            let s = dir.run(&dir.config("testmt"), "kv", || {
+                info!(shard = 7, owner = "cache team"; "rebalanced");
+            })?;
+
+            assert!(s.ends_with("rebalanced owner=\"cache team\" shard=7\n"), "{}", s);
+
+            Ok(())
+        })
     }
 
+    #[cfg(feature = "kv")]
     rusty_fork_test! {
         #[test]
-        fn test_named_threads() { named_threads_test(); }
+        fn test_key_values() { key_values_test(); }
+    }
+
+    #[test]
+    fn test_format_json_escapes_message() {
+        assert_result(|| {
+            let cfg = harness::config()
+                .format(LogFormat::Json)
+                .sequence_numbers(true)
+                .build()?;
+
+            let msg = "say \"hi\"\\ then\nnew line\ttab \u{1} bell ✓";
+            let line = LineInfo {
+                seq: Some(12),
+                now: Some(time::at_utc(time::Timespec::new(1_500_000_000, 500_000_000))),
+                ..LineInfo::default()
+            };
+
+            let out = harness::format_line(
+                &cfg,
+                &Record::builder()
+                    .args(format_args!("{}", msg))
+                    .level(Level::Warn)
+                    .module_path(Some("ccommon::\"quoted\""))
+                    .build(),
+                &line,
+            )?;
+            assert!(out.ends_with("}\n"));
+            assert_eq!(out.lines().count(), 1);
+
+            let v: serde_json::Value = serde_json::from_str(&out)?;
+            assert_eq!(v["seq"], 12);
+            assert_eq!(v["ts"], "2017-07-14 02:40:00.500000");
+            assert_eq!(v["level"], "WARN");
+            assert_eq!(v["module"], "ccommon::\"quoted\"");
+            assert_eq!(v["msg"], msg);
+
+            Ok(())
+        })
+    }
+
+    fn json_format_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+
+            let mut cfg = dir.config("testmt");
+            cfg.format(LogFormat::Json);
+            let s = dir.run(&cfg, "json", || {
+                info!("plain");
+                error!("with \"quotes\" and\nlines");
+            })?;
+
+            let recs: Vec<serde_json::Value> =
+                s.lines()
+                    .map(serde_json::from_str)
+                    .collect::<::std::result::Result<_, _>>()?;
+
+            assert_eq!(recs.len(), 2);
+            assert_eq!(recs[0]["level"], "INFO");
+            assert_eq!(recs[0]["msg"], "plain");
+            assert_eq!(recs[1]["level"], "ERROR");
+            assert_eq!(recs[1]["module"], module_path!());
+            assert_eq!(recs[1]["msg"], "with \"quotes\" and\nlines");
+            assert!(recs[1].get("seq").is_none());
+
+            Ok(())
+        })
     }
 
     rusty_fork_test! {
         #[test]
-        fn test_shutdown_resilience() { mt_shutdown_resilience_test(); }
+        fn test_json_format() { json_format_test(); }
+    }
+
+    #[test]
+    fn test_format_host_and_pid() {
+        assert_result(|| {
+            let format_with = |cfg: &LogConfig| -> Result<String> {
+                harness::format_line(
+                    cfg,
+                    &Record::builder().args(format_args!("tagged")).level(Level::Info).build(),
+                    &LineInfo::default(),
+                )
+            };
+
+            let cfg = harness::config().host_and_pid(true).build()?;
+            let tag = format!("{}[{}] ", hostname(), process::id());
+            assert!(!hostname().is_empty());
+
+            let out = format_with(&cfg)?;
+            assert!(out.starts_with(&tag), "{}", out);
+            assert!(out.ends_with("INFO  [] tagged\n"), "{}", out);
+
+            let out = format_with(&harness::config().build()?)?;
+            assert!(!out.contains(&tag), "{}", out);
+
+            let json_cfg = harness::config()
+                .host_and_pid(true)
+                .format(LogFormat::Json)
+                .build()?;
+            let rec: serde_json::Value = serde_json::from_str(&format_with(&json_cfg)?)?;
+            assert_eq!(rec["host"], hostname());
+            assert_eq!(rec["pid"], process::id());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_format_local_time_offset() {
+        assert_result(|| {
+            let cfg = harness::config().clock(Clock::Local).build()?;
+
+            let format_with_offset = |utcoff: i32| -> Result<String> {
+                let mut tm = time::at_utc(time::Timespec::new(1_500_000_000, 0));
+                tm.tm_utcoff = utcoff;
+                let line = LineInfo { now: Some(tm), ..LineInfo::default() };
+                harness::format_line(
+                    &cfg,
+                    &Record::builder().args(format_args!("tz")).level(Level::Info).build(),
+                    &line,
+                )
+            };
+
+            let out = format_with_offset(-7 * 3600)?;
+            assert!(out.starts_with("2017-07-14 02:40:00.000000 -0700 INFO "), "{}", out);
+            let out = format_with_offset(5 * 3600 + 30 * 60)?;
+            assert!(out.starts_with("2017-07-14 02:40:00.000000 +0530 INFO "), "{}", out);
+            let out = format_with_offset(0)?;
+            assert!(out.starts_with("2017-07-14 02:40:00.000000 +0000 INFO "), "{}", out);
+
+            // and with the real local time, whatever the zone is
+            let out = harness::format_line(
+                &cfg,
+                &Record::builder().args(format_args!("now")).level(Level::Info).build(),
+                &LineInfo::default(),
+            )?;
+            let off: Vec<&str> = out.split(' ').collect();
+            assert_eq!(off[2].len(), 5, "{}", out);
+            assert!(off[2].starts_with('+') || off[2].starts_with('-'), "{}", out);
+            assert!(off[2][1..].chars().all(|c| c.is_ascii_digit()), "{}", out);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_format_replaces_previous_line() {
+        assert_result(|| {
+            let cfg = harness::config().build()?;
+
+            // calls format directly: the point is reusing one buffer
+            let mut buf = Vec::with_capacity(PER_THREAD_BUF_SIZE);
+            let long = "x".repeat(1000);
+            for msg in &[&long[..], "short"] {
+                let sz = format(
+                    &Record::builder().args(format_args!("{}", msg)).level(Level::Info).build(),
+                    &mut buf,
+                    &cfg,
+                    &LineInfo::default(),
+                )?;
+                assert_eq!(sz, buf.len());
+            }
+
+            let out = str::from_utf8(&buf)?;
+            assert!(out.ends_with(" short\n"), "{}", out);
+            assert!(!out.contains('x'));
+            // the allocation is reused, not shrunk
+            assert!(buf.capacity() >= PER_THREAD_BUF_SIZE);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_format_multi_line_continuation() {
+        assert_result(|| {
+            let cfg = harness::config()
+                .continuation_prefix(String::from("... | "))
+                .build()?;
+
+            let out = harness::format_line(
+                &cfg,
+                &Record::builder()
+                    .args(format_args!("first\nsecond\nthird"))
+                    .level(Level::Error)
+                    .module_path(Some("ccommon::test"))
+                    .build(),
+                &LineInfo::default(),
+            )?;
+            let lines: Vec<&str> = out.lines().collect();
+            assert_eq!(lines.len(), 3);
+            assert!(lines[0].ends_with("ERROR [ccommon::test] first"), "{}", lines[0]);
+            assert_eq!(lines[1], "... | second");
+            assert_eq!(lines[2], "... | third");
+
+            Ok(())
+        })
+    }
+
+    // runs this test with process isolation
+    rusty_fork_test! {
+        #[test]
+        fn test_basic_mt_roundtrip() { basic_mt_roundtrip(); }
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_named_threads() { named_threads_test(); }
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_shutdown_resilience() { mt_shutdown_resilience_test(); }
+    }
+
+    fn shutdown_waits_for_readers_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+
+            // a big buffer, so nothing reaches the file unless we flush
+            let cfg = dir.config("testmt")
+                .buf_size(64 * 1024)
+                .build()?;
+
+            let handle = log_setup_safe(cfg).unwrap();
+
+            harness::on_thread("buffered", move || {
+                info!("buffered line");
+            });
+
+            let logp = dir.path().join("testmt.buffered.log");
+            assert_eq!(fs::read_to_string(&logp)?, "");
+
+            // stand in for a thread that's in the middle of logging when
+            // shutdown starts
+            let held = handle.shim.get();
+            let (held_tx, held_rx) = mpsc::channel();
+            let holder = thread::spawn(move || {
+                held_tx.send(()).unwrap();
+                thread::sleep(::std::time::Duration::from_millis(50));
+                drop(held);
+            });
+            held_rx.recv().unwrap();
+
+            // blocks until the holder lets go, then flushes
+            drop(handle);
+            assert!(fs::read_to_string(&logp)?.contains("buffered line"));
+            holder.join().unwrap();
+
+            Ok(())
+        })
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_shutdown_waits_for_readers() { shutdown_waits_for_readers_test(); }
+    }
+
+    fn shutdown_backoff_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+
+            let cfg = dir.config("testmt").build()?;
+
+            let mut handle = log_setup_safe(cfg).unwrap();
+
+            let held = handle.shim.get();
+            let holder = thread::spawn(move || {
+                thread::sleep(::std::time::Duration::from_millis(200));
+                drop(held);
+            });
+
+            let start = time::SteadyTime::now();
+            handle.shutdown(time::Duration::milliseconds(SHUTDOWN_TIMEOUT_MS as i64));
+            let elapsed = time::SteadyTime::now() - start;
+            holder.join().unwrap();
+
+            // we waited for the holder, but got in soon after it let go
+            assert!(elapsed >= time::Duration::milliseconds(150), "{}", elapsed);
+            assert!(
+                elapsed < time::Duration::milliseconds(200 + 10 * SHUTDOWN_BACKOFF_CAP_MS as i64),
+                "{}", elapsed
+            );
+            assert!(!handle.is_setup());
+
+            Ok(())
+        })
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_shutdown_backoff() { shutdown_backoff_test(); }
+    }
+
+    fn flush_interval_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+
+            // a big buffer, so nothing reaches the file unless it's flushed
+            let cfg = dir.config("testmt")
+                .buf_size(64 * 1024)
+                .flush_interval(time::Duration::milliseconds(50))
+                .build()?;
+
+            let mut handle = log_setup_safe(cfg).unwrap();
+
+            harness::on_thread("quiet", move || {
+                info!("flushed in the background");
+            });
+
+            let logp = dir.path().join("testmt.quiet.log");
+            let deadline = time::SteadyTime::now() + time::Duration::seconds(5);
+            while !fs::read_to_string(&logp)?.contains("flushed in the background") {
+                assert!(time::SteadyTime::now() < deadline, "never flushed");
+                thread::sleep(::std::time::Duration::from_millis(10));
+            }
+
+            handle.shutdown(time::Duration::milliseconds(SHUTDOWN_TIMEOUT_MS as i64));
+            assert!(handle.flusher.is_none());
+
+            Ok(())
+        })
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_flush_interval() { flush_interval_test(); }
+    }
+
+    #[test]
+    fn test_flush_interval_must_be_positive() {
+        for ms in &[0, -50] {
+            let res = harness::config()
+                .flush_interval(time::Duration::milliseconds(*ms))
+                .build();
+            assert!(res.is_err(), "{}ms was accepted", ms);
+        }
+    }
+
+    #[test]
+    fn test_rotated_path() {
+        assert_eq!(
+            LogConfig::rotated_path(Path::new("/var/log/cc.main.log"), 1),
+            PathBuf::from("/var/log/cc.main.1.log"));
+        assert_eq!(
+            LogConfig::rotated_path(Path::new("/var/log/cc.main.log"), 12),
+            PathBuf::from("/var/log/cc.main.12.log"));
+        assert_eq!(
+            LogConfig::rotated_path(Path::new("/var/log/custom"), 2),
+            PathBuf::from("/var/log/custom.2"));
+    }
+
+    fn rotation_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+
+            let mut cfg = dir.config("testmt");
+            cfg.max_file_bytes(1024)
+                .retained_files(2);
+
+            let current = dir.run(&cfg, "rot", || {
+                for i in 0..200 {
+                    info!("line {:04} padding padding padding", i);
+                }
+            })?;
+
+            let first = dir.read("testmt.rot.1.log")?;
+            let second = dir.read("testmt.rot.2.log")?;
+            assert!(!dir.path().join("testmt.rot.3.log").exists());
+
+            assert!(first.len() > 1024);
+            assert!(second.len() > 1024);
+            assert!(current.len() < first.len());
+
+            // the newest lines are in the current file, the older ones rolled off
+            assert!(current.contains("line 0199"));
+            assert!(second.contains("line 0"));
+            let all = [current, first, second].concat();
+            assert!(!all.contains("line 0000 "));
+
+            Ok(())
+        })
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_rotation() { rotation_test(); }
+    }
+
+    fn set_level_from_c_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+
+            let cfg = dir.config("testmt").build()?;
+
+            let mut ph = Box::into_raw(Box::new(log_setup_safe(cfg).unwrap()));
+
+            unsafe {
+                assert_eq!(log_set_level_rs(ptr::null_mut(), 1), LoggerStatus::NullPointerError);
+                assert_eq!(log_set_level_rs(ph, 0), LoggerStatus::InvalidLevel);
+                assert_eq!(log_set_level_rs(ph, 6), LoggerStatus::InvalidLevel);
+                assert_eq!(log_set_level_rs(ph, bind::log_level_rs_LOG_LEVEL_ERROR), LoggerStatus::OK);
+            }
+            assert_eq!(rslog::max_level(), Level::Error);
+
+            harness::on_thread("set_level", move || {
+                warn!("should be filtered");
+                error!("should be written");
+            });
+
+            unsafe { log_destroy_handle_rs(&mut ph) };
+
+            let content = dir.read("testmt.set_level.log")?;
+            assert!(content.contains("should be written"));
+            assert!(!content.contains("should be filtered"));
+
+            Ok(())
+        })
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_set_level_from_c() { set_level_from_c_test(); }
+    }
+
+    fn effective_levels_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+
+            let cfg = dir.config("ccommon")
+                .level(Level::Warn)
+                .build()?;
+
+            let mut handle = log_setup_safe(cfg).unwrap();
+            assert_eq!(effective_levels(), vec![Level::Error, Level::Warn]);
+            assert_eq!(handle.effective_levels(), vec![Level::Error, Level::Warn]);
+
+            handle.shutdown(time::Duration::milliseconds(SHUTDOWN_TIMEOUT_MS as i64));
+            assert!(handle.effective_levels().is_empty());
+
+            Ok(())
+        })
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_effective_levels() { effective_levels_test(); }
+    }
+
+    fn get_level_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+
+            let cfg = dir.config("ccommon")
+                .level(Level::Info)
+                .build()?;
+
+            let mut ph = Box::into_raw(Box::new(log_setup_safe(cfg).unwrap()));
+            assert_eq!(current_level(), Some(Level::Info));
+            assert_eq!(log_get_level_rs(), bind::log_level_rs_LOG_LEVEL_INFO);
+
+            unsafe {
+                assert_eq!(log_set_level_rs(ph, bind::log_level_rs_LOG_LEVEL_WARN), LoggerStatus::OK);
+            }
+            assert_eq!(current_level(), Some(Level::Warn));
+            assert_eq!(log_get_level_rs(), bind::log_level_rs_LOG_LEVEL_WARN);
+
+            rslog::set_max_level(rslog::LevelFilter::Off);
+            assert_eq!(current_level(), None);
+            assert_eq!(log_get_level_rs(), 0);
+
+            unsafe { log_destroy_handle_rs(&mut ph) };
+
+            Ok(())
+        })
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_get_level() { get_level_test(); }
+    }
+
+    fn flush_on_panic_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+
+            let cfg = dir.config("testmt")
+                .buf_size(4096)
+                .flush_on_panic(true)
+                .build()?;
+
+            let handle = log_setup_safe(cfg).unwrap();
+
+            // a thread that never logged has nothing to flush, and shouldn't
+            // get a file just for panicking. this goes first: thread_local
+            // recycles ids, so a later thread could inherit panicky's slot
+            let res = build("silent").spawn(move || {
+                panic!("no last words");
+            }).unwrap().join();
+            assert!(res.is_err());
+            assert!(!dir.path().join("testmt.silent.log").exists());
+
+            // a panic while the hook's lock is held poisons it, which
+            // mustn't turn the hook off for the rest of the process
+            let res = build("poisoner").spawn(move || {
+                let _installed = INSTALLED.lock().unwrap();
+                panic!("while holding the lock");
+            }).unwrap().join();
+            assert!(res.is_err());
+            assert!(INSTALLED.is_poisoned());
+
+            let res = build("panicky").spawn(move || {
+                error!("last words");
+                panic!("oh no");
+            }).unwrap().join();
+            assert!(res.is_err());
+
+            // the handle is still alive, so only the panic hook could have flushed
+            assert!(dir.read("testmt.panicky.log")?.contains("last words"));
+
+            drop(handle);
+            Ok(())
+        })
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_flush_on_panic() { flush_on_panic_test(); }
+    }
+
+    fn banner_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+
+            let mut cfg = dir.config("testmt");
+            cfg.banner(String::from("testapp v1.2.3 pid=42"));
+            let s = dir.run(&cfg, "bannered", || {
+                info!("after the banner");
+            })?;
+
+            let lines: Vec<&str> = s.lines().collect();
+            assert_eq!(lines.len(), 2);
+            assert_eq!(lines[0], "testapp v1.2.3 pid=42");
+            assert!(lines[1].ends_with("after the banner"));
+
+            Ok(())
+        })
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_banner_is_first_line() { banner_test(); }
+    }
+
+    fn filename_fn_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+
+            let mut cfg = dir.config("ccommon");
+            cfg.filename_fn(|thread| {
+                format!("{}.{}.log", time::strftime("%Y%m%d", &time::now_utc()).unwrap(), thread)
+            });
+
+            let s = dir.run(&cfg, "named", || {
+                info!("custom filename");
+            })?;
+
+            let date = time::strftime("%Y%m%d", &time::now_utc()).unwrap();
+            assert!(dir.path().join(format!("{}.named.log", date)).exists());
+            assert!(s.contains("custom filename"));
+
+            Ok(())
+        })
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_filename_fn() { filename_fn_test(); }
+    }
+
+    fn open_failure_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+
+            static OPENS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+            let cfg = dir.config("ccommon")
+                .filename_fn(|thread| {
+                    OPENS.fetch_add(1, Ordering::SeqCst);
+                    format!("{}.log", thread)
+                })
+                .build()?;
+
+            // the directory checked out at build time, but is gone by the
+            // time a thread opens its file
+            fs::remove_dir(dir.path())?;
+
+            let mut handle = log_setup_safe(cfg).unwrap();
+
+            harness::on_thread("doomed", move || {
+                for i in 0..10 {
+                    info!("line {}", i);
+                }
+                rslog::logger().flush();
+            });
+
+            assert_eq!(OPENS.load(Ordering::SeqCst), 1);
+
+            handle.shutdown(time::Duration::milliseconds(SHUTDOWN_TIMEOUT_MS as i64));
+
+            Ok(())
+        })
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_open_failure_is_cached() { open_failure_test(); }
+    }
+
+    fn reopen_after_failed_open_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+            let logdir = dir.path().join("logs");
+            fs::create_dir(&logdir)?;
+
+            let cfg = LogConfigBuilder::default()
+                .path(logdir.to_str().unwrap().to_owned())
+                .prefix(String::from("testmt"))
+                .build()?;
+
+            fs::remove_dir(&logdir)?;
+
+            let mut handle = log_setup_safe(cfg).unwrap();
+
+            let (logged_tx, logged_rx) = mpsc::channel();
+            let (go_tx, go_rx) = mpsc::channel();
+
+            let t = build("revived").spawn(move || {
+                info!("nowhere to go");
+                logged_tx.send(()).unwrap();
+                go_rx.recv().unwrap();
+                info!("back in business");
+            }).unwrap();
+
+            logged_rx.recv().unwrap();
+            fs::create_dir(&logdir)?;
+            assert!(handle.reopen());
+            go_tx.send(()).unwrap();
+            t.join().unwrap();
+
+            handle.shutdown(time::Duration::milliseconds(SHUTDOWN_TIMEOUT_MS as i64));
+
+            let s = fs::read_to_string(logdir.join("testmt.revived.log"))?;
+            assert!(s.contains("back in business"));
+            assert!(!s.contains("nowhere to go"));
+
+            Ok(())
+        })
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_reopen_after_failed_open() { reopen_after_failed_open_test(); }
+    }
+
+    fn format_fn_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+
+            let mut cfg = dir.config("ccommon");
+            cfg.format_fn(|record, buf| {
+                let lvl = record.level().to_string();
+                writeln!(buf, "{} {}", &lvl[..1], record.args())?;
+                Ok(buf.len())
+            });
+
+            let s = dir.run(&cfg, "custom", || {
+                warn!("disk at {}%", 91);
+                info!("recovered");
+            })?;
+
+            assert_eq!(s, "W disk at 91%\nI recovered\n");
+
+            Ok(())
+        })
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_format_fn() { format_fn_test(); }
+    }
+
+    fn format_error_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+
+            let mut cfg = dir.config("ccommon");
+            cfg.format_fn(|record, buf| {
+                let msg = record.args().to_string();
+                if msg == "unformattable" {
+                    bail!("refusing to format {:?}", msg);
+                }
+                writeln!(buf, "{}", msg)?;
+                Ok(buf.len())
+            });
+
+            let s = dir.run(&cfg, "fmterr", || {
+                info!("before");
+                info!("unformattable");
+                info!("after");
+            })?;
+
+            assert_eq!(s, "before\nafter\n");
+
+            Ok(())
+        })
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_format_error_drops_record() { format_error_test(); }
+    }
+
+    fn flush_all_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+
+            // a big buffer, so nothing reaches the file unless it's flushed
+            let cfg = dir.config("testmt")
+                .buf_size(64 * 1024)
+                .build()?;
+
+            let mut ph = Box::into_raw(Box::new(log_setup_safe(cfg).unwrap()));
+
+            let (logged_tx, logged_rx) = mpsc::channel();
+            let (done_tx, done_rx) = mpsc::channel::<()>();
+            let done_rx = Arc::new(Mutex::new(done_rx));
+
+            let threads: Vec<_> = ["one", "two"].iter().map(|name| {
+                let logged_tx = logged_tx.clone();
+                let done_rx = done_rx.clone();
+                build(name).spawn(move || {
+                    info!("buffered");
+                    logged_tx.send(()).unwrap();
+                    // stay alive until the files have been checked
+                    let _ = done_rx.lock().unwrap().recv();
+                }).unwrap()
+            }).collect();
+
+            logged_rx.recv().unwrap();
+            logged_rx.recv().unwrap();
+
+            assert!(!dir.read("testmt.one.log")?.contains("buffered"));
+
+            unsafe {
+                assert_eq!(log_flush_all_rs(ptr::null_mut()), LoggerStatus::NullPointerError);
+                assert_eq!(log_flush_all_rs(ph), LoggerStatus::OK);
+            }
+
+            assert!(dir.read("testmt.one.log")?.contains("buffered"));
+            assert!(dir.read("testmt.two.log")?.contains("buffered"));
+
+            drop(done_tx);
+            for t in threads {
+                t.join().unwrap();
+            }
+
+            unsafe {
+                assert_eq!(log_shutdown_rs(ph, 1000), LoggerStatus::OK);
+                assert_eq!(log_flush_all_rs(ph), LoggerStatus::LoggerNotSetupError);
+                log_destroy_handle_rs(&mut ph);
+            }
+
+            Ok(())
+        })
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_flush_all() { flush_all_test(); }
+    }
+
+    fn reopen_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+
+            let cfg = dir.config("testmt").build()?;
+
+            let mut handle = log_setup_safe(cfg).unwrap();
+
+            let (logged_tx, logged_rx) = mpsc::channel();
+            let (go_tx, go_rx) = mpsc::channel();
+
+            let t = build("rotated").spawn(move || {
+                info!("before rotation");
+                logged_tx.send(()).unwrap();
+                go_rx.recv().unwrap();
+                info!("after rotation");
+            }).unwrap();
+
+            let logp = dir.path().join("testmt.rotated.log");
+            let rotated = dir.path().join("testmt.rotated.log.1");
+
+            logged_rx.recv().unwrap();
+            fs::rename(&logp, &rotated)?;
+            assert!(handle.reopen());
+            go_tx.send(()).unwrap();
+            t.join().unwrap();
+
+            handle.shutdown(time::Duration::milliseconds(SHUTDOWN_TIMEOUT_MS as i64));
+            assert!(!handle.reopen());
+
+            let old = fs::read_to_string(&rotated)?;
+            assert!(old.contains("before rotation"));
+            assert!(!old.contains("after rotation"));
+
+            let new = fs::read_to_string(&logp)?;
+            assert!(!new.contains("before rotation"));
+            assert!(new.contains("after rotation"));
+
+            Ok(())
+        })
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_reopen() { reopen_test(); }
+    }
+
+    #[test]
+    fn test_dropped_count() {
+        assert_result(|| {
+            let h = LogTestHarness::with_buf_size(16)?;
+
+            let before = dropped_count();
+            // doesn't fit in the buffer, so it's skipped
+            assert!(!h.write(b"this line is longer than sixteen bytes\n"));
+            assert!(dropped_count() > before);
+            assert!(log_dropped_count_rs() >= dropped_count() as u64);
+
+            // but the logger is still usable for lines that do fit
+            assert!(h.write(b"short\n"));
+            assert_eq!(h.read_all()?, "short\n");
+
+            Ok(())
+        })
+    }
+
+    fn flush_stats_test() {
+        assert_result(|| {
+            let h = LogTestHarness::with_buf_size(4096)?;
+
+            // disabled by default, so nothing is recorded
+            h.flush();
+            assert_eq!(flush_stats(), FlushStats::default());
+
+            set_flush_timing(true);
+            for _ in 0..3 {
+                assert!(h.write(b"some data\n"));
+                h.flush();
+            }
+
+            let stats = flush_stats();
+            assert_eq!(stats.count, 3);
+            assert!(stats.max_ns <= stats.total_ns);
+
+            // C reads the same numbers
+            unsafe {
+                let mut from_c = FlushStats::default();
+                assert_eq!(log_get_flush_stats_rs(&mut from_c), LoggerStatus::OK);
+                assert_eq!(from_c, stats);
+                assert_eq!(log_get_flush_stats_rs(ptr::null_mut()), LoggerStatus::NullPointerError);
+
+                log_set_flush_timing_rs(false);
+                h.flush();
+                assert_eq!(flush_stats().count, 3);
+            }
+
+            Ok(())
+        })
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_flush_stats() { flush_stats_test(); }
+    }
+
+    fn config_builder_ffi_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+
+            let mut b = log_config_builder_create_rs();
+            unsafe {
+                {
+                    // these are dropped before the handle is created, which is
+                    // fine since the builder copies them
+                    let path = CString::new(dir.path().to_str().unwrap())?;
+                    let prefix = CString::new("fromc")?;
+                    assert_eq!(log_config_builder_path_rs(b, path.as_ptr()), LoggerStatus::OK);
+                    assert_eq!(log_config_builder_prefix_rs(b, prefix.as_ptr()), LoggerStatus::OK);
+                }
+                assert_eq!(log_config_builder_buf_size_rs(b, 1024), LoggerStatus::OK);
+                assert_eq!(log_config_builder_level_rs(b, 99), LoggerStatus::InvalidLevel);
+                assert_eq!(log_config_builder_level_rs(b, 2), LoggerStatus::OK);
+                assert_eq!(log_config_builder_path_rs(b, ptr::null()), LoggerStatus::NullPointerError);
+
+                let mut ph = log_create_handle_from_builder_rs(b);
+                assert!(!ph.is_null());
+                // the handle doesn't borrow from the builder
+                log_config_builder_destroy_rs(&mut b);
+                assert!(b.is_null());
+
+                harness::on_thread("ffi", move || {
+                    warn!("kept");
+                    info!("filtered");
+                });
+
+                assert_eq!(log_shutdown_rs(ph, 1000), LoggerStatus::OK);
+                log_destroy_handle_rs(&mut ph);
+                assert!(ph.is_null());
+            }
+
+            let s = dir.read("fromc.ffi.log")?;
+            assert!(s.contains("kept"));
+            assert!(!s.contains("filtered"));
+
+            Ok(())
+        })
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_config_builder_ffi() { config_builder_ffi_test(); }
+    }
+
+    fn safe_setup_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+
+            let mut cfg = LogConfig::builder();
+            cfg.path(dir.path().to_str().unwrap().to_owned())
+                .prefix(String::from("safe"))
+                .buf_size(1024)
+                .level(Level::Info);
+
+            let s = dir.run(&cfg, "main", || {
+                info!("set up from rust");
+                debug!("below the level");
+            })?;
+            assert!(s.contains("set up from rust"));
+            assert!(!s.contains("below the level"));
+
+            Ok(())
+        })
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_safe_setup() { safe_setup_test(); }
+    }
+
+    #[test]
+    fn test_build_checks_log_dir() {
+        let tmpdir = tempfile::tempdir().unwrap();
+
+        let missing = tmpdir.path().join("nope");
+        assert!(LogConfig::builder().path(missing.to_str().unwrap().to_owned()).build().is_err());
+
+        let file = tmpdir.path().join("file");
+        fs::write(&file, b"").unwrap();
+        assert!(LogConfig::builder().path(file.to_str().unwrap().to_owned()).build().is_err());
+
+        assert!(LogConfig::builder().build().is_err());
+        assert!(LogConfig::builder().path(tmpdir.path().to_str().unwrap().to_owned()).build().is_ok());
+    }
+
+    #[test]
+    fn test_logging_error_to_status() {
+        let cases = vec![
+            (LoggingError::LoggingAlreadySetUp, LoggerStatus::LoggerAlreadySetError),
+            (LoggingError::LoggerRegistrationFailure, LoggerStatus::RegistrationFailure),
+            (
+                LoggingError::CreationError { path: String::from("/tmp"), buf_size: 0 },
+                LoggerStatus::CreationError,
+            ),
+            (
+                LoggingError::LogDirUnavailable { path: String::from("/nope"), reason: String::new() },
+                LoggerStatus::LogDirUnavailable,
+            ),
+        ];
+
+        for (err, status) in cases {
+            assert_eq!(LoggerStatus::from(err), status);
+        }
+    }
+
+    fn dir_error(b: &LogConfigBuilder) -> LoggingError {
+        match b.build() {
+            Ok(_) => panic!("build should have failed"),
+            Err(e) => e.downcast::<LoggingError>().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_build_creates_missing_log_dir() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let nested = tmpdir.path().join("a").join("b");
+        let path = nested.to_str().unwrap().to_owned();
+
+        match dir_error(LogConfig::builder().path(path.clone())) {
+            LoggingError::LogDirUnavailable { path: ref p, .. } => assert_eq!(*p, path),
+            e => panic!("unexpected error: {:?}", e),
+        }
+        assert!(!nested.exists());
+
+        LogConfig::builder().path(path).create_dir(true).build().unwrap();
+        assert!(nested.is_dir());
+    }
+
+    #[test]
+    fn test_build_rejects_file_as_log_dir() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let file = tmpdir.path().join("file");
+        fs::write(&file, b"").unwrap();
+
+        let err = dir_error(LogConfig::builder().path(file.to_str().unwrap().to_owned()).create_dir(true));
+        match err {
+            LoggingError::LogDirUnavailable { .. } => (),
+            e => panic!("unexpected error: {:?}", e),
+        }
+        assert_eq!(LoggerStatus::from(err), LoggerStatus::LogDirUnavailable);
+    }
+
+    mod db {
+        pub fn emit() {
+            debug!("db debug");
+            trace!("db trace");
+        }
+    }
+
+    // shares a name prefix with `db` but must not pick up its filter
+    mod dbx {
+        pub fn emit() {
+            debug!("dbx debug");
+            warn!("dbx warn");
+        }
+    }
+
+    mod web {
+        pub fn emit() {
+            debug!("web debug");
+            info!("web info");
+        }
+    }
+
+    #[test]
+    fn test_module_level_matches_whole_segments() {
+        assert_result(|| {
+            let cfg = harness::config()
+                .module_filter(String::from("mycrate"), Level::Info)
+                .module_filter(String::from("mycrate::hot"), Level::Warn)
+                .build()?;
+
+            assert_eq!(cfg.module_level("mycrate"), Some(Level::Info));
+            assert_eq!(cfg.module_level("mycrate::hot"), Some(Level::Warn));
+            assert_eq!(cfg.module_level("mycrate::hot::path"), Some(Level::Warn));
+            assert_eq!(cfg.module_level("mycrate::hotel"), Some(Level::Info));
+            assert_eq!(cfg.module_level("mycrate_ext"), None);
+            assert_eq!(cfg.module_level("other"), None);
+
+            Ok(())
+        })
+    }
+
+    fn module_filters_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+
+            let cfg = dir.config("testmt")
+                .level(Level::Info)
+                .module_filter(module_path!().to_owned(), Level::Warn)
+                .module_filter(format!("{}::db", module_path!()), Level::Debug)
+                .build()?;
+
+            let mut handle = log_setup_safe(cfg).unwrap();
+            assert_eq!(rslog::max_level(), Level::Debug);
+
+            harness::on_thread("filtered", move || {
+                db::emit();
+                dbx::emit();
+                web::emit();
+                warn!("test warn");
+                info!("test info");
+            });
+
+            handle.shutdown(time::Duration::milliseconds(SHUTDOWN_TIMEOUT_MS as i64));
+
+            let logp = dir.path().join("testmt.filtered.log");
+            let s = fs::read_to_string(logp)?;
+
+            // db gets its own, more verbose level
+            assert!(s.contains("db debug"));
+            assert!(!s.contains("db trace"));
+            // dbx falls back to the test-module filter, not db's
+            assert!(!s.contains("dbx debug"));
+            assert!(s.contains("dbx warn"));
+            // web only matches the shorter test-module filter
+            assert!(!s.contains("web debug"));
+            assert!(!s.contains("web info"));
+            assert!(s.contains("test warn"));
+            assert!(!s.contains("test info"));
+
+            Ok(())
+        })
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_module_filters() { module_filters_test(); }
+    }
+
+    fn filtered_before_open_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+
+            // the db filter raises the global max level, so the log crate
+            // hands us web's debug and info records and we have to drop them
+            let cfg = dir.config("testmt")
+                .level(Level::Warn)
+                .module_filter(format!("{}::db", module_path!()), Level::Debug)
+                .build()?;
+
+            let mut handle = log_setup_safe(cfg).unwrap();
+            assert_eq!(rslog::max_level(), Level::Debug);
+
+            let web_info =
+                Metadata::builder()
+                    .level(Level::Info)
+                    .target(concat!(module_path!(), "::web"))
+                    .build();
+            assert!(!rslog::logger().enabled(&web_info));
+
+            harness::on_thread("quiet", move || {
+                web::emit();
+            });
+
+            handle.shutdown(time::Duration::milliseconds(SHUTDOWN_TIMEOUT_MS as i64));
+
+            // the thread never got far enough to open its file
+            assert!(!dir.path().join("testmt.quiet.log").exists());
+
+            Ok(())
+        })
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_filtered_before_open() { filtered_before_open_test(); }
+    }
+
+    fn sequence_numbers_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+
+            let mut cfg = dir.config("testmt");
+            cfg.sequence_numbers(true);
+            let s = dir.run(&cfg, "seq", || {
+                for i in 0..5 {
+                    info!("line {}", i);
+                }
+            })?;
+
+            let seqs: Vec<u64> =
+                s.lines()
+                    .map(|l| l.split(' ').next().unwrap().parse().unwrap())
+                    .collect();
+            assert_eq!(seqs, vec![0, 1, 2, 3, 4]);
+
+            Ok(())
+        })
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_sequence_numbers() { sequence_numbers_test(); }
+    }
+
+    fn resetup_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+
+            let mut handle = log_setup_safe(dir.config("first").build()?).unwrap();
+
+            // a second setup while the first is active is refused
+            assert!(log_setup_safe(dir.config("again").build()?).is_err());
+
+            harness::on_thread("resetup", || info!("from the first logger"));
+            handle.shutdown(time::Duration::milliseconds(SHUTDOWN_TIMEOUT_MS as i64));
+            assert!(!handle.is_setup());
+
+            let mut handle = log_setup_safe(dir.config("second").build()?).unwrap();
+            assert!(handle.is_setup());
+
+            harness::on_thread("resetup", || info!("from the second logger"));
+            handle.shutdown(time::Duration::milliseconds(SHUTDOWN_TIMEOUT_MS as i64));
+
+            let first = dir.read("first.resetup.log")?;
+            let second = dir.read("second.resetup.log")?;
+            assert!(first.contains("from the first logger"));
+            assert!(!first.contains("from the second logger"));
+            assert!(second.contains("from the second logger"));
+
+            Ok(())
+        })
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_resetup_after_shutdown() { resetup_test(); }
+    }
+
+    fn stale_handle_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+
+            unsafe {
+                let mut first = Box::into_raw(Box::new(log_setup_safe(dir.config("first").build()?).unwrap()));
+                assert_eq!(log_shutdown_rs(first, 1000), LoggerStatus::OK);
+
+                let mut second = log_setup_safe(dir.config("second").build()?).unwrap();
+
+                // the first handle shares the cell but mustn't reach the new logger
+                assert!(!log_is_active_rs(first));
+                assert_eq!(log_reopen_rs(first), LoggerStatus::LoggerNotSetupError);
+                assert_eq!(log_flush_all_rs(first), LoggerStatus::LoggerNotSetupError);
+                assert_eq!(log_set_level_rs(first, Level::Error as u32), LoggerStatus::LoggerNotSetupError);
+                assert!((*first).effective_levels().is_empty());
+                assert_eq!(log_shutdown_rs(first, 1000), LoggerStatus::OK);
+                log_destroy_handle_rs(&mut first);
+
+                assert!(second.is_setup());
+                assert_eq!(second.effective_levels().len(), effective_levels().len());
+                harness::on_thread("stale", || info!("still logging"));
+                second.shutdown(time::Duration::milliseconds(SHUTDOWN_TIMEOUT_MS as i64));
+            }
+
+            let second = dir.read("second.stale.log")?;
+            assert!(second.contains("still logging"));
+
+            Ok(())
+        })
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_stale_handle_leaves_new_logger_alone() { stale_handle_test(); }
+    }
+
+    fn is_active_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+
+            unsafe {
+                assert!(!log_is_active_rs(ptr::null_mut()));
+
+                let cfg = dir.config("testmt").build()?;
+                let mut ph = Box::into_raw(Box::new(log_setup_safe(cfg).unwrap()));
+                assert!(log_is_active_rs(ph));
+
+                assert_eq!(log_shutdown_rs(ph, 1000), LoggerStatus::OK);
+                assert!(!log_is_active_rs(ph));
+                log_destroy_handle_rs(&mut ph);
+
+                let cfg = dir.config("testmt").build()?;
+                let mut ph = Box::into_raw(Box::new(log_setup_safe(cfg).unwrap()));
+                assert!(log_is_active_rs(ph));
+                log_destroy_handle_rs(&mut ph);
+            }
+
+            Ok(())
+        })
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_is_active() { is_active_test(); }
+    }
+
+    fn monotonic_clock_test() {
+        assert_result(|| {
+            let dir = MtLogDir::new()?;
+
+            let mut cfg = dir.config("testmt");
+            cfg.clock(Clock::Monotonic);
+            let s = dir.run(&cfg, "mono", || {
+                for i in 0..100 {
+                    info!("tick {}", i);
+                }
+            })?;
+
+            let stamps: Vec<u64> =
+                s.lines()
+                    .map(|l| l.split(' ').next().unwrap().parse().unwrap())
+                    .collect();
+            assert_eq!(stamps.len(), 100);
+            assert!(stamps.windows(2).all(|w| w[0] <= w[1]));
+
+            Ok(())
+        })
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_monotonic_clock() { monotonic_clock_test(); }
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_ensure_cc_log_setup_runs_once() {
+            assert!(ensure_cc_log_setup());
+            assert!(!ensure_cc_log_setup());
+        }
     }
 }
 
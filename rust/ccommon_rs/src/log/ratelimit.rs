@@ -0,0 +1,120 @@
+// ccommon - a cache common library.
+// Copyright (C) 2018 Twitter, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rate limiting for the logger's own error reporting. When a log file
+//! becomes unwritable every log call fails the same way, and printing each
+//! failure to stderr just trades one problem for another.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use time;
+
+const WINDOW_MS: usize = 1000;
+const PER_WINDOW: usize = 5;
+
+pub enum Verdict {
+    /// Print the message. `suppressed` messages were dropped since the
+    /// last one that was printed.
+    Emit { suppressed: usize },
+    Suppress,
+}
+
+/// Allows `PER_WINDOW` messages per `WINDOW_MS`, counting the rest.
+pub struct ErrorLimiter {
+    window_start_ms: AtomicUsize,
+    in_window: AtomicUsize,
+    suppressed: AtomicUsize,
+}
+
+impl ErrorLimiter {
+    pub const fn new() -> Self {
+        ErrorLimiter {
+            window_start_ms: AtomicUsize::new(0),
+            in_window: AtomicUsize::new(0),
+            suppressed: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn check(&self) -> Verdict {
+        self.check_at((time::precise_time_ns() / 1_000_000) as usize)
+    }
+
+    fn check_at(&self, now_ms: usize) -> Verdict {
+        let start = self.window_start_ms.load(Ordering::Relaxed);
+        if now_ms >= start + WINDOW_MS {
+            // only the thread that moves the window resets the count
+            if self.window_start_ms
+                .compare_exchange(start, now_ms, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+            {
+                self.in_window.store(0, Ordering::Relaxed);
+            }
+        }
+
+        if self.in_window.fetch_add(1, Ordering::Relaxed) < PER_WINDOW {
+            Verdict::Emit { suppressed: self.suppressed.swap(0, Ordering::Relaxed) }
+        } else {
+            self.suppressed.fetch_add(1, Ordering::Relaxed);
+            Verdict::Suppress
+        }
+    }
+}
+
+/// `eprintln!`, limited to a few messages per second per call site. When
+/// messages have been dropped, a count of them is printed before the next
+/// one that gets through.
+macro_rules! limited_eprintln {
+    ($($arg:tt)*) => {{
+        static LIMITER: $crate::log::ratelimit::ErrorLimiter =
+            $crate::log::ratelimit::ErrorLimiter::new();
+
+        if let $crate::log::ratelimit::Verdict::Emit { suppressed } = LIMITER.check() {
+            if suppressed > 0 {
+                eprintln!("({} similar errors suppressed)", suppressed);
+            }
+            eprintln!($($arg)*);
+        }
+    }};
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn emitted(v: &Verdict) -> Option<usize> {
+        match *v {
+            Verdict::Emit { suppressed } => Some(suppressed),
+            Verdict::Suppress => None,
+        }
+    }
+
+    #[test]
+    fn test_burst_is_bounded() {
+        let lim = ErrorLimiter::new();
+        let base = 1_000_000;
+
+        let printed = (0..1000)
+            .map(|i| lim.check_at(base + i % 10))
+            .filter(|v| emitted(v).is_some())
+            .count();
+        assert_eq!(printed, PER_WINDOW);
+
+        // the next window reports what was dropped in the last one
+        let v = lim.check_at(base + WINDOW_MS);
+        assert_eq!(emitted(&v), Some(1000 - PER_WINDOW));
+
+        let v = lim.check_at(base + WINDOW_MS + 1);
+        assert_eq!(emitted(&v), Some(0));
+    }
+}
@@ -0,0 +1,115 @@
+// ccommon - a cache common library.
+// Copyright (C) 2018 Twitter, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use rslog::{Log, Metadata, Record};
+
+/// Fans records out to several `Log` backends, e.g. the cc_log logger plus
+/// stderr or an in-memory collector. Each backend still applies its own
+/// `enabled` check.
+pub struct TeeLogger {
+    backends: Vec<Box<dyn Log>>,
+}
+
+impl TeeLogger {
+    pub fn new(backends: Vec<Box<dyn Log>>) -> Self {
+        TeeLogger { backends }
+    }
+
+    pub fn push(&mut self, backend: Box<dyn Log>) {
+        self.backends.push(backend);
+    }
+}
+
+impl Log for TeeLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.backends.iter().any(|b| b.enabled(metadata))
+    }
+
+    fn log(&self, record: &Record) {
+        for b in &self.backends {
+            if b.enabled(record.metadata()) {
+                b.log(record);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        for b in &self.backends {
+            b.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rslog::Level;
+    use std::sync::{Arc, Mutex};
+
+    struct Collector {
+        max: Level,
+        lines: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Log for Collector {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= self.max
+        }
+
+        fn log(&self, record: &Record) {
+            self.lines.lock().unwrap().push(format!("{} {}", record.level(), record.args()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn collector(max: Level) -> (Box<dyn Log>, Arc<Mutex<Vec<String>>>) {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        (Box::new(Collector { max, lines: lines.clone() }), lines)
+    }
+
+    fn emit(log: &dyn Log, level: Level, msg: &str) {
+        log.log(&Record::builder().args(format_args!("{}", msg)).level(level).build());
+    }
+
+    #[test]
+    fn test_tee_forwards_to_all_backends() {
+        let (a, a_lines) = collector(Level::Trace);
+        let (b, b_lines) = collector(Level::Trace);
+        let tee = TeeLogger::new(vec![a, b]);
+
+        emit(&tee, Level::Info, "one");
+        emit(&tee, Level::Error, "two");
+
+        let expected = vec![String::from("INFO one"), String::from("ERROR two")];
+        assert_eq!(*a_lines.lock().unwrap(), expected);
+        assert_eq!(*b_lines.lock().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_tee_respects_each_backend_level() {
+        let (a, a_lines) = collector(Level::Warn);
+        let (b, b_lines) = collector(Level::Debug);
+        let tee = TeeLogger::new(vec![a, b]);
+
+        assert!(tee.enabled(&Metadata::builder().level(Level::Debug).build()));
+        assert!(!tee.enabled(&Metadata::builder().level(Level::Trace).build()));
+
+        emit(&tee, Level::Debug, "chatty");
+
+        assert!(a_lines.lock().unwrap().is_empty());
+        assert_eq!(*b_lines.lock().unwrap(), vec![String::from("DEBUG chatty")]);
+    }
+}
@@ -13,12 +13,23 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::fmt;
 use std::result;
 use std::ptr;
 
+/// Returned when a pointer we were handed is NULL. Carries the name of the
+/// pointer, if the check was given one, so the error says which it was.
 #[derive(Fail, Debug)]
-#[fail(display = "Null pointer exception")]
-pub struct NullPointerError;
+pub struct NullPointerError(pub Option<&'static str>);
+
+impl fmt::Display for NullPointerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(name) => write!(f, "Null pointer exception: {}", name),
+            None => f.write_str("Null pointer exception"),
+        }
+    }
+}
 
 pub fn lift_to_option<T>(p: *mut T) -> Option<*mut T> {
     if p.is_null() {
@@ -29,7 +40,12 @@ pub fn lift_to_option<T>(p: *mut T) -> Option<*mut T> {
 }
 
 pub fn null_check<T>(p: *mut T) -> result::Result<*mut T, NullPointerError> {
-    lift_to_option(p).ok_or_else(|| NullPointerError)
+    lift_to_option(p).ok_or_else(|| NullPointerError(None))
+}
+
+/// Like `null_check`, but the error names `p` as `name`.
+pub fn null_check_named<T>(p: *mut T, name: &'static str) -> result::Result<*mut T, NullPointerError> {
+    lift_to_option(p).ok_or_else(|| NullPointerError(Some(name)))
 }
 
 pub fn opt_to_null_mut<T>(o: Option<*mut T>) -> *mut T {
@@ -39,3 +55,20 @@ pub fn opt_to_null_mut<T>(o: Option<*mut T>) -> *mut T {
     }
 }
 
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_null_check_named() {
+        let mut x = 1u8;
+        assert!(null_check_named(&mut x as *mut u8, "x").is_ok());
+
+        let err = null_check_named(ptr::null_mut::<u8>(), "log_config_rs").unwrap_err();
+        assert_eq!(err.to_string(), "Null pointer exception: log_config_rs");
+
+        let err = null_check(ptr::null_mut::<u8>()).unwrap_err();
+        assert_eq!(err.to_string(), "Null pointer exception");
+    }
+}